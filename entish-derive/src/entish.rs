@@ -1,15 +1,22 @@
 use proc_macro2::{Ident, TokenStream};
 
 use std::collections::HashSet;
-use std::iter::FromIterator;
 
-use syn::{fold::{Fold, fold_fields_named, fold_fields_unnamed}, Generics, Variant, Type, GenericParam, TypeParam, punctuated::Punctuated, token::Comma, Fields, FieldsNamed, FieldsUnnamed, Attribute, DeriveInput, Meta, MetaList, Path, NestedMeta, TypeParamBound, Index};
+use syn::{fold::Fold, spanned::Spanned, ext::IdentExt, Generics, Variant, Type, TypePath, TypeParen, TypeGroup, TypeTuple, TypeArray, Expr, ExprLit, Index, GenericParam, GenericArgument, PathArguments, AngleBracketedGenericArguments, TypeParam, punctuated::Punctuated, token::Comma, Fields, Field, Attribute, DeriveInput, Data, DataEnum, DataStruct, DataUnion, Meta, MetaList, MetaNameValue, Lit, Path, NestedMeta, LifetimeDef, ConstParam, FieldsNamed, FieldsUnnamed, TypeParamBound, Error};
 
-use crate::utils::{MentionedGenerics, ReplaceIdent, FindIdent, map_fields};
+use crate::utils::{MentionedGenerics, ReplaceIdent, ReplaceType, FindIdent, Place, map_fields, zip_fields, field_places, destructure_fields, destructure_other_fields, destructure_params, collect_errors, snake_name, snake_case};
 
-const SELF: &'static str = "Self";
-const CHILD: &'static str = "Child";
-const MAP_OUTPUT: &'static str = "MapOutput";
+const SELF: &str = "Self";
+const CHILD: &str = "Child";
+const MAP_OUTPUT: &str = "MapOutput";
+const OTHER_CHILD: &str = "OtherChild";
+/// The type parameter of the `*Visit` and `*Fold` traits for the trees they
+/// visit or fold, which does not shadow types of the user
+const TREE_PARAM: &str = "__EntishTree";
+const HELD_PARAM: &str = "__EntishHeld";
+
+/// The traits that `#[entish(derive_tree(..))]` implements on the tree wrapper
+const TREE_DERIVES: [&str; 5] = ["PartialEq", "Eq", "PartialOrd", "Ord", "Hash"];
 
 macro_rules! generic_param {
     ($e:expr) => {
@@ -36,17 +43,14 @@ fn contains_ident(ty: &Type, ident: &Ident) -> bool {
     find_ident.matched()
 }
 
-fn add_bound_to_all<'a, I>(iter: I, lt: TypeParamBound) -> ()
+fn add_bound_to_all<'a, I>(iter: I, lt: TypeParamBound)
 where
     I: Iterator<Item = &'a mut GenericParam>
 {
     iter.for_each(|param| {
-        match param {
-            GenericParam::Type(tp) => {
-                tp.bounds.push(lt.clone());
-            },
-            _ => {}
-        };
+        if let GenericParam::Type(tp) = param {
+            tp.bounds.push(lt.clone());
+        }
     })
 }
 
@@ -56,37 +60,63 @@ where
 {
     iter.filter(move |gp| {
         match gp {
-            GenericParam::Type(tp) => {
-                if tp.ident == ident {
-                    false
-                } else {
-                    true
-                }
-            },
+            GenericParam::Type(tp) => tp.ident != ident,
             _ => true
         }
     })
 }
 
-fn where_clause_for_generics<'a, I>(iter: I) -> Option<TokenStream>
+/// `Clone` bounds on the types of the fields that do not hold children of
+/// the types `mapped` but mention a type parameter of `generics`, which the
+/// borrowing [Map](../entish/trait.Map.html) impls need to clone.
+fn clone_predicates<'a, I>(generics: &Generics, fields: I, mapped: &[&Ident]) -> Punctuated<TokenStream, Comma>
 where
-    I: Iterator<Item = &'a GenericParam>
+    I: Iterator<Item = &'a Field>
 {
-    let where_predicates: Punctuated<TokenStream, Comma> = iter
-        .filter_map(|gp| {
-            match gp {
-                GenericParam::Type(TypeParam { ident, .. }) => {
-                    if ident != &format_ident!("{}", CHILD) {
-                        Some(quote! { #ident: Clone })
-                    } else {
-                        None
-                    }
-                },
-                _ => None
-            }
-        })
+    label_predicates(generics, fields, mapped, quote! { Clone })
+}
+
+/// `Clone` and `PartialEq` bounds on the same types as [clone_predicates],
+/// which the [Zip](../entish/trait.Zip.html) impls need to compare and clone.
+fn zip_predicates<'a, I>(generics: &Generics, fields: I, child_ident: &Ident) -> Punctuated<TokenStream, Comma>
+where
+    I: Iterator<Item = &'a Field> + Clone
+{
+    let mut predicates = clone_predicates(generics, fields.clone(), &[child_ident]);
+    predicates.extend(label_predicates(generics, fields, &[child_ident], quote! { PartialEq }));
+    predicates
+}
+
+/// `bound` on the types of the fields that do not hold children of the
+/// types `mapped` but mention a type parameter of `generics`
+fn label_predicates<'a, I>(
+    generics: &Generics,
+    fields: I,
+    mapped: &[&Ident],
+    bound: TokenStream
+) -> Punctuated<TokenStream, Comma>
+where
+    I: Iterator<Item = &'a Field>
+{
+    let type_params: Vec<&Ident> = generics
+        .type_params()
+        .map(|TypeParam { ident, .. }| ident)
+        .filter(|ident| !mapped.contains(ident))
         .collect();
 
+    let mut seen = HashSet::new();
+    fields
+        .map(|field| &field.ty)
+        .filter(|ty| {
+            !mapped.iter().any(|ident| contains_ident(ty, ident)) &&
+                type_params.iter().any(|ident| contains_ident(ty, ident))
+        })
+        .filter(|ty| seen.insert(quote! { #ty }.to_string()))
+        .map(|ty| quote! { #ty: #bound })
+        .collect()
+}
+
+fn where_clause(where_predicates: Punctuated<TokenStream, Comma>) -> Option<TokenStream> {
     if ! where_predicates.is_empty() {
         Some(quote! { where #where_predicates })
     } else {
@@ -94,671 +124,3601 @@ where
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy)]
-enum SupportedDerives {
-    TryInto,
-    From,
-    Map,
-    MapOwned,
-    IntoResult,
-    IntoOption
+/// Whether `ty` is one of `idents`
+fn is_any_ident(ty: &Type, idents: &[&Ident]) -> bool {
+    idents.iter().any(|ident| is_ident(ty, ident))
 }
 
-impl SupportedDerives {
-    fn try_from(p: &Path) -> Option<Self> {
-        match p.get_ident()?.to_string().as_str() {
-            "TryInto" => Some(Self::TryInto),
-            "From" => Some(Self::From),
-            "Map" => Some(Self::Map),
-            "MapOwned" => Some(Self::MapOwned),
-            "IntoResult" => Some(Self::IntoResult),
-            "IntoOption" => Some(Self::IntoOption),
-            _ => None
-        }
+/// Whether `ty` mentions one of `idents`
+fn contains_any_ident(ty: &Type, idents: &[&Ident]) -> bool {
+    idents.iter().any(|ident| contains_ident(ty, ident))
+}
+
+/// Check that `ty` is either one of `idents`, the types of children, does
+/// not mention them, or holds them in a way that the traits in `entish` know
+/// how to map over: a tuple or an array of types holding them, or a
+/// container with a single type argument holding them, such as `Vec<Child>`
+/// or `BTreeMap<String, Option<Child>>`. Children can only be the values of
+/// maps, and cannot be held in sets, which they would be the keys of.
+fn check_child_type(ty: &Type, idents: &[&Ident]) -> syn::Result<()> {
+    if is_any_ident(ty, idents) || !contains_any_ident(ty, idents) {
+        return Ok(())
+    }
+
+    let unsupported = || Error::new_spanned(
+        ty,
+        "unsupported field type: children must be `Self`, or be held in a tuple, an array or a container \
+         of `Self`, such as `Vec<Self>`"
+    );
+
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => {
+            let (last, mut init) = match path.segments.last() {
+                Some(last) => (last, path.segments.iter().take(path.segments.len() - 1)),
+                None => return Err(unsupported())
+            };
+            if init.any(|segment| idents.iter().any(|ident| {
+                let mut find_ident = FindIdent::new((*ident).clone());
+                find_ident.fold_path_segment(segment.clone());
+                find_ident.matched()
+            })) {
+                return Err(unsupported())
+            }
+            if last.ident == "HashSet" || last.ident == "BTreeSet" {
+                return Err(Error::new_spanned(
+                    ty,
+                    format!(
+                        "unsupported field type: children cannot be held in a `{}`, whose elements are \
+                         keys, use a `Vec` or the values of a map instead",
+                        last.ident
+                    )
+                ))
+            }
+            let args = match &last.arguments {
+                PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) => args,
+                _ => return Err(unsupported())
+            };
+            let mut holding = Vec::new();
+            for (idx, arg) in args.iter().enumerate() {
+                match arg {
+                    GenericArgument::Type(ty) if contains_any_ident(ty, idents) => holding.push((idx, ty)),
+                    GenericArgument::Type(_) | GenericArgument::Lifetime(_) | GenericArgument::Const(_) => (),
+                    _ => return Err(unsupported())
+                }
+            }
+            match holding.as_slice() {
+                [(0, key)] if last.ident == "BTreeMap" || last.ident == "HashMap" => Err(Error::new_spanned(
+                    key,
+                    "unsupported field type: children can only be the values of a map, as in \
+                     `BTreeMap<String, Self>`"
+                )),
+                [(_, elem)] => check_child_type(elem, idents),
+                _ => Err(Error::new_spanned(
+                    ty,
+                    "unsupported field type: children must all be in the same type argument of their \
+                     container, as in `BTreeMap<String, Self>`"
+                ))
+            }
+        },
+        Type::Tuple(TypeTuple { elems, .. }) => {
+            collect_errors(elems.iter().map(|elem| check_child_type(elem, idents)))
+        },
+        Type::Array(TypeArray { elem, .. }) |
+        Type::Paren(TypeParen { elem, .. }) |
+        Type::Group(TypeGroup { elem, .. }) => check_child_type(elem, idents),
+        _ => Err(unsupported())
     }
 }
 
-#[derive(Debug)]
-pub struct NodeBuilder {
-    generics: Generics,
-    variant: Variant,
-    relevant: MentionedGenerics,
+/// How a field type holds children, see [holder]
+enum Holder<'t> {
+    /// It is a child
+    Child,
+    /// It holds no child
+    Nothing,
+    /// A tuple of these types, some of which hold children
+    Tuple(Vec<&'t Type>),
+    /// A container of elements of this type, which holds children, e.g.
+    /// `Option<Child>` for `Vec<Option<Child>>`
+    Container(&'t Type)
 }
 
-pub struct Node {
-    ident: Ident,
-    generics: Generics,
-    fields: Fields
+/// How `ty`, accepted by [check_child_type], holds children. Containers are
+/// mapped over by the traits in `entish`, with closures handling their
+/// elements in turn, while tuples are taken apart by the generated code.
+fn holder<'t>(ty: &'t Type, child_ident: &Ident) -> Holder<'t> {
+    holder_of_any(ty, &[child_ident])
 }
 
-impl NodeBuilder {
-    pub fn from_variant(generics: &Generics, variant: &Variant) -> Self {
-        Self {
-            generics: generics.clone(),
-            variant: variant.clone(),
-            relevant: MentionedGenerics::default()
-        }
+/// Like [holder] but for children of any of the types `idents`
+fn holder_of_any<'t>(ty: &'t Type, idents: &[&Ident]) -> Holder<'t> {
+    if is_any_ident(ty, idents) {
+        return Holder::Child
+    }
+    if !contains_any_ident(ty, idents) {
+        return Holder::Nothing
+    }
+    match ty {
+        Type::Paren(TypeParen { elem, .. }) | Type::Group(TypeGroup { elem, .. }) => holder_of_any(elem, idents),
+        Type::Tuple(TypeTuple { elems, .. }) => Holder::Tuple(elems.iter().collect()),
+        Type::Array(TypeArray { elem, .. }) => Holder::Container(elem),
+        Type::Path(TypePath { path, .. }) => {
+            let elem = path.segments.last().and_then(|segment| match &segment.arguments {
+                PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) => {
+                    args.iter().find_map(|arg| match arg {
+                        GenericArgument::Type(ty) if contains_any_ident(ty, idents) => Some(ty),
+                        _ => None
+                    })
+                },
+                _ => None
+            });
+            Holder::Container(elem.expect("checked by `check_child_type`"))
+        },
+        _ => unreachable!("checked by `check_child_type`")
     }
+}
 
-    pub fn extend_closure<'a, I: IntoIterator<Item = &'a Ident>>(
-        &mut self,
-        i: I
-    ) -> &mut Self {
-        self.relevant.targets.extend(i.into_iter().cloned());
-        self
+/// `ty` with `child_ident` replaced by `with`, e.g. to annotate the
+/// parameters of closures taking elements of containers whose children have
+/// been replaced
+fn replace_child(ty: &Type, child_ident: &Ident, with: TokenStream) -> Type {
+    ReplaceType::replace_with(child_ident.clone(), syn::parse2(with).unwrap()).fold_type(ty.clone())
+}
+
+/// The number of children held in `ty`, if it is always the same
+fn fixed_arity(ty: &Type, child_ident: &Ident) -> Option<usize> {
+    match holder(ty, child_ident) {
+        Holder::Child => Some(1),
+        Holder::Nothing => Some(0),
+        Holder::Tuple(elems) => elems.into_iter().map(|elem| fixed_arity(elem, child_ident)).sum(),
+        Holder::Container(elem) => match ty {
+            Type::Array(TypeArray { len: Expr::Lit(ExprLit { lit: Lit::Int(len), .. }), .. }) => {
+                Some(len.base10_parse::<usize>().ok()? * fixed_arity(elem, child_ident)?)
+            },
+            Type::Path(TypePath { path, .. }) if path.segments.last().is_some_and(|s| s.ident == "Box") => {
+                fixed_arity(elem, child_ident)
+            },
+            _ => None
+        }
     }
+}
 
-    pub fn generate(mut self) -> Node {
-        let mut out = TokenStream::new();
+/// The expression mapping the children held in `ty`, borrowed for `'a` by
+/// `place`, in [Map](../entish/trait.Map.html)
+fn map_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place) },
+        // assumes has to be cloned, which is an error pointing at the type
+        // of the field if it cannot be
+        Holder::Nothing => quote_spanned! { ty.span() => std::clone::Clone::clone(#place) },
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                map_expr(child_ident, elem, quote! { &(#place).#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            quote! { <#ty as entish::Map<&'a #elem, _>>::map(#place, f) }
+        },
+        Holder::Container(elem) => {
+            let inner = map_expr(child_ident, elem, quote! { __inner });
+            quote! { <#ty as entish::Map<&'a #elem, _>>::map(#place, &mut |__inner: &'a #elem| #inner) }
+        }
+    }
+}
 
-        syn::fold::fold_variant(&mut self.relevant, self.variant.clone());
-        let closure = self.relevant.into_mentioned();
+/// The expression borrowing the children of all the types `idents` held in
+/// `ty`, borrowed for `'a` by `place`, in `as_refs`
+fn as_refs_expr(idents: &[&Ident], ty: &Type, place: TokenStream) -> TokenStream {
+    match holder_of_any(ty, idents) {
+        Holder::Child => place,
+        Holder::Nothing => quote_spanned! { ty.span() => std::clone::Clone::clone(#place) },
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                as_refs_expr(idents, elem, quote! { &(#place).#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(elem) => {
+            let inner = as_refs_expr(idents, elem, quote! { __inner });
+            quote! { <#ty as entish::Map<&'a #elem, _>>::map(#place, &mut |__inner: &'a #elem| #inner) }
+        }
+    }
+}
 
-        let ident = self.variant.ident;
+/// The expression mapping the children held in `ty`, moved out of `place`,
+/// in [MapOwned](../entish/trait.MapOwned.html)
+fn map_owned_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place) },
+        Holder::Nothing => place,
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                map_owned_expr(child_ident, elem, quote! { #place.#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            quote! { <#ty as entish::MapOwned<#elem, _>>::map_owned(#place, f) }
+        },
+        Holder::Container(elem) => {
+            let inner = map_owned_expr(child_ident, elem, quote! { __inner });
+            quote! { <#ty as entish::MapOwned<#elem, _>>::map_owned(#place, &mut |__inner: #elem| #inner) }
+        }
+    }
+}
 
-        let generics: Generics = {
-            let mut params = Punctuated::<GenericParam, Comma>::new();
-            let params = self.generics
-                .params
-                .into_iter()
-                .filter_map(|p| match p {
-                    GenericParam::Type(TypeParam { ident, .. }) if closure.contains(&ident) => {
-                        Some(generic_param!(ident))
-                    },
-                    _ => None
-                })
-                .collect();
-            Generics {
-                params,
-                ..Default::default()
-            }
-        };
+/// The statements mapping the children held in `ty`, borrowed mutably for
+/// `'a` by `place`, in [MapMut](../entish/trait.MapMut.html)
+fn map_mut_stmts(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place); },
+        Holder::Nothing => quote! {},
+        Holder::Tuple(elems) => elems
+            .into_iter()
+            .enumerate()
+            .map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                map_mut_stmts(child_ident, elem, quote! { &mut (*#place).#idx })
+            })
+            .collect(),
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            quote! { <#ty as entish::MapMut<#elem>>::map_mut(#place, f); }
+        },
+        Holder::Container(elem) => {
+            let inner = map_mut_stmts(child_ident, elem, quote! { __inner });
+            quote! { <#ty as entish::MapMut<#elem>>::map_mut(#place, &mut |__inner: &'a mut #elem| { #inner }); }
+        }
+    }
+}
 
-        Node { ident, generics, fields: self.variant.fields }
+/// The expression traversing the children held in `ty`, moved out of
+/// `place`, in [Traverse](../entish/trait.Traverse.html)
+fn traverse_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place)? },
+        Holder::Nothing => place,
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                traverse_expr(child_ident, elem, quote! { #place.#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(_) => {
+            let traversed = try_traverse_expr(child_ident, ty, place);
+            quote! { #traversed? }
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct EntishBuilder {
-    ident: Ident,
-    attributes: Vec<Attribute>,
-    generics: Generics,
-    nodes: Vec<NodeBuilder>,
-    variants_as_structs: bool
+/// Like [traverse_expr] but for a `ty` that holds children, with the result
+/// of traversing them instead of returning early on errors
+fn try_traverse_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place) },
+        Holder::Nothing | Holder::Tuple(_) => {
+            let traversed = traverse_expr(child_ident, ty, place);
+            quote! { Ok(#traversed) }
+        },
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            quote! { <#ty as entish::Traverse<#elem, _>>::try_traverse(#place, f) }
+        },
+        Holder::Container(elem) => {
+            let inner = try_traverse_expr(child_ident, elem, quote! { __inner });
+            quote! {
+                <#ty as entish::Traverse<#elem, _>>::try_traverse(
+                    #place,
+                    &mut |__inner: #elem| -> std::result::Result<_, __Error> { #inner }
+                )
+            }
+        }
+    }
 }
 
-impl EntishBuilder {
-    pub fn new(input: &DeriveInput) -> Self {
+/// The expression pairing up the children held in `ty`, borrowed for `'a`
+/// by `place` and `other_place`, in [Zip](../entish/trait.Zip.html)
+fn zip_expr(child_ident: &Ident, ty: &Type, place: TokenStream, other_place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { f(#place, #other_place) },
+        // labels must be equal, which is an error pointing at the type of
+        // the field if they cannot be compared
+        Holder::Nothing => quote_spanned! { ty.span() =>
+            {
+                if std::cmp::PartialEq::ne(#place, #other_place) {
+                    return None
+                }
+                std::clone::Clone::clone(#place)
+            }
+        },
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                zip_expr(child_ident, elem, quote! { &(#place).#idx }, quote! { &(#other_place).#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(_) => {
+            let zipped = try_zip_expr(child_ident, ty, place, other_place);
+            quote! { #zipped? }
+        }
+    }
+}
 
-        let ident = input.ident.clone();
+/// Like [zip_expr] but for a `ty` that holds children, with `None` instead
+/// of returning early if they do not have the same shape
+fn try_zip_expr(child_ident: &Ident, ty: &Type, place: TokenStream, other_place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child | Holder::Nothing | Holder::Tuple(_) => {
+            let zipped = zip_expr(child_ident, ty, place, other_place);
+            quote! { Some(#zipped) }
+        },
+        Holder::Container(elem) => {
+            let other_elem = replace_child(elem, child_ident, quote! { OtherChild });
+            let zip = quote! { <#ty as entish::Zip<&'a #elem, &'a #other_elem, _>>::zip_with };
+            if is_ident(elem, child_ident) {
+                return quote! { #zip(#place, #other_place, f) }
+            }
+            // the elements may not have the same shape either
+            let inner = try_zip_expr(child_ident, elem, quote! { __inner }, quote! { __other });
+            quote! {
+                entish::IntoOption::into_option(
+                    #zip(#place, #other_place, &mut |__inner: &'a #elem, __other: &'a #other_elem| #inner)?
+                )
+            }
+        }
+    }
+}
 
-        let mut generics = input.generics.clone();
-        generics.params.push(generic_param!(format_ident!("{}", CHILD)).into());
+/// How children are taken out of the results or options they are replaced
+/// by, in [IntoResult](../entish/trait.IntoResult.html) and
+/// [IntoOption](../entish/trait.IntoOption.html)
+struct Unwrap {
+    /// The conversion of a container of results or options
+    convert: TokenStream,
+    /// The type replacing children
+    child: TokenStream,
+    /// The type of the result or option of an element of a container
+    output: TokenStream,
+    /// The constructor of a successful result or option
+    wrap: TokenStream
+}
 
-        let attributes = input.attrs.clone();
+impl Unwrap {
+    fn result() -> Self {
+        Self {
+            convert: quote! { entish::IntoResult::into_result },
+            child: quote! { std::result::Result<Child, __Error> },
+            output: quote! { std::result::Result<_, __Error> },
+            wrap: quote! { Ok }
+        }
+    }
 
+    fn option() -> Self {
         Self {
-            ident,
-            attributes,
-            generics,
-            nodes: Vec::new(),
-            variants_as_structs: true
+            convert: quote! { entish::IntoOption::into_option },
+            child: quote! { Option<Child> },
+            output: quote! { Option<_> },
+            wrap: quote! { Some }
         }
     }
+}
 
-    pub fn get_generic_idents(&self) -> impl Iterator<Item = &'_ Ident> + '_ {
-        self.generics
-            .params
-            .iter()
-            .filter_map(|gp| {
-                match gp {
-                    GenericParam::Type(TypeParam { ident, .. }) => {
-                        Some(ident)
-                    },
-                    _ => None
-                }
-            })
+/// The expression taking the children held in `ty` out of the results or
+/// options moved out of `place`, returning early if one of them failed
+fn unwrap_expr(child_ident: &Ident, ty: &Type, place: TokenStream, unwrap: &Unwrap) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { #place? },
+        Holder::Nothing => place,
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                unwrap_expr(child_ident, elem, quote! { #place.#idx }, unwrap)
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(_) => {
+            let unwrapped = try_unwrap_expr(child_ident, ty, place, unwrap);
+            quote! { #unwrapped? }
+        }
     }
+}
 
-    pub fn add_node(&mut self, variant: &Variant) -> &mut Self {
-        let mut ri = ReplaceIdent::replace_with(
-            format_ident!("{}", SELF),
-            format_ident!("{}", CHILD)
-        );
+/// Like [unwrap_expr] but for a `ty` that holds children, with the result or
+/// option of all of them instead of returning early
+fn try_unwrap_expr(child_ident: &Ident, ty: &Type, place: TokenStream, unwrap: &Unwrap) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => place,
+        Holder::Nothing | Holder::Tuple(_) => {
+            let wrap = &unwrap.wrap;
+            let unwrapped = unwrap_expr(child_ident, ty, place, unwrap);
+            quote! { #wrap(#unwrapped) }
+        },
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            let convert = &unwrap.convert;
+            quote! { #convert(#place) }
+        },
+        Holder::Container(elem) => {
+            let Unwrap { convert, child, output, .. } = unwrap;
+            let elem_ty = replace_child(elem, child_ident, child.clone());
+            let inner = try_unwrap_expr(child_ident, elem, quote! { __inner }, unwrap);
+            quote! {
+                #convert(entish::MapOwned::map_owned(#place, &mut |__inner: #elem_ty| -> #output { #inner }))
+            }
+        }
+    }
+}
 
-        let variant = ri.fold_variant(variant.clone());
-        let mut node = NodeBuilder::from_variant(&self.generics, &variant);
+/// The expression of type `Result<_, Vec<__Error>>` taking the children
+/// held in `ty`, which is not `Nothing`, out of the results moved out of
+/// `place`, with the errors of all of them, in
+/// [IntoValidated](../entish/trait.IntoValidated.html)
+fn validated_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child | Holder::Nothing => place,
+        Holder::Tuple(elems) => {
+            let bindings: Vec<Ident> = (0..elems.len()).map(|idx| format_ident!("__valid_{}", idx)).collect();
+            let checks = elems.into_iter().zip(bindings.iter()).enumerate().map(|(idx, (elem, binding))| {
+                let idx = Index::from(idx);
+                if contains_ident(elem, child_ident) {
+                    let validated = validated_expr(child_ident, elem, quote! { #place.#idx });
+                    quote! { let #binding = #validated.map_err(|e| errors.extend(e)).ok(); }
+                } else {
+                    quote! { let #binding = Some(#place.#idx); }
+                }
+            });
+            quote! {
+                {
+                    let mut errors = Vec::new();
+                    #(#checks)*
+                    if errors.is_empty() {
+                        Ok((#(#bindings.unwrap(),)*))
+                    } else {
+                        Err(errors)
+                    }
+                }
+            }
+        },
+        Holder::Container(elem) if is_ident(elem, child_ident) => {
+            quote! { entish::IntoValidated::into_validated(#place) }
+        },
+        Holder::Container(elem) => {
+            let elem_ty = replace_child(elem, child_ident, quote! { std::result::Result<#child_ident, Vec<__Error>> });
+            let inner = validated_expr(child_ident, elem, quote! { __inner });
+            quote! {
+                entish::IntoValidated::into_validated(
+                    entish::MapOwned::map_owned(#place, &mut |__inner: #elem_ty| #inner)
+                )
+            }
+        }
+    }
+}
 
-        node.extend_closure(self.get_generic_idents());
+/// The expression for a field of a node in [Map](../entish/trait.Map.html)
+fn map_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    map_expr(child_ident, &field.ty, quote! { &#place })
+}
 
-        self.nodes.push(node);
-        self
-    }
+/// The expression for a field of a node in [MapOwned](../entish/trait.MapOwned.html)
+fn map_owned_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    map_owned_expr(child_ident, &field.ty, place.clone())
+}
 
-    pub fn generate(mut self) -> TokenStream {
-        let mut out = TokenStream::new();
+/// The expression for a field of a node in [Zip](../entish/trait.Zip.html),
+/// paired with the same field of the other node at `other_place`
+fn zip_field(child_ident: &Ident, place: &TokenStream, other_place: &TokenStream, field: &Field) -> TokenStream {
+    zip_expr(child_ident, &field.ty, quote! { &#place }, quote! { &#other_place })
+}
 
-        let child_ident = format_ident!("{}", CHILD);
+/// The statement for a field of a node in [MapMut](../entish/trait.MapMut.html),
+/// bound by mutable reference at `place`
+fn map_mut_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    map_mut_stmts(child_ident, &field.ty, place.clone())
+}
 
-        let c_ident = self.ident;
-        let c_generics = self.generics;
+/// The expression for a field of a node in [Traverse](../entish/trait.Traverse.html)
+fn traverse_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    traverse_expr(child_ident, &field.ty, place.clone())
+}
 
-        let mut metas = Vec::new();
-        let mut derives = HashSet::new();
+/// The expression for a field of a node in [IntoResult](../entish/trait.IntoResult.html)
+fn into_result_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    unwrap_expr(child_ident, &field.ty, place.clone(), &Unwrap::result())
+}
 
-        let trait_ident = format_ident!("{}Tree", c_ident);
+/// The expression for a field of a node in [IntoOption](../entish/trait.IntoOption.html)
+fn into_option_field(child_ident: &Ident, place: &TokenStream, field: &Field) -> TokenStream {
+    unwrap_expr(child_ident, &field.ty, place.clone(), &Unwrap::option())
+}
 
-        // sift through attributes; remove all paths in #[derive(..)]
-        // if path is supported
-        for attribute in self.attributes.into_iter() {
-            let mut meta = attribute.parse_meta().unwrap();
-            match &mut meta {
-                Meta::List(MetaList { path, nested, .. }) => {
-                    if path.get_ident() == Some(&format_ident!("derive")) {
-                        *nested = nested
-                            .iter()
-                            .cloned()
-                            .filter_map(|nested_meta| match nested_meta {
-                                NestedMeta::Meta(Meta::Path(p)) => {
-                                    match SupportedDerives::try_from(&p) {
-                                        Some(derive) => {
-                                            derives.insert(derive);
-                                            None
-                                        },
-                                        None => Some(NestedMeta::Meta(Meta::Path(p)))
-                                    }
-                                },
-                                _ => panic!("only paths allowed here")
-                            })
-                            .collect();
-                    } else if path.get_ident() == Some(&format_ident!("entish")) {
-                        continue
-                    }
-                },
-                _ => {}
+/// The expression for a node in [IntoValidated](../entish/trait.IntoValidated.html)
+/// built with `path` from its `fields`, bound as per `Place::Binding`. Returns
+/// early with the errors of all children if any of them failed.
+fn validated_node(child_ident: &Ident, path: &TokenStream, fields: &Fields) -> TokenStream {
+    let checks: Vec<TokenStream> = field_places(fields, Place::Binding)
+        .into_iter()
+        .filter(|(_, field)| contains_ident(&field.ty, child_ident))
+        .map(|(place, field)| {
+            let validated = validated_expr(child_ident, &field.ty, place.clone());
+            quote! {
+                let #place = #validated.map_err(|e| errors.extend(e)).ok();
             }
-            metas.push(meta);
+        })
+        .collect();
+
+    let mapped_fields = map_fields(fields, Place::Binding, |place, field| {
+        if contains_ident(&field.ty, child_ident) {
+            // checked above
+            quote! { #place.unwrap() }
+        } else {
+            quote! { #place }
         }
+    });
 
-        let attributes: TokenStream = metas.into_iter()
-            .map(|meta| quote! { #[#meta] })
-            .collect();
+    if checks.is_empty() {
+        return quote! { #path #mapped_fields }
+    }
 
-        let mut variants = Punctuated::<Variant, Comma>::new();
+    quote! {
+        {
+            let mut errors = Vec::new();
+            #(#checks)*
+            if !errors.is_empty() {
+                return Err(errors)
+            }
+            #path #mapped_fields
+        }
+    }
+}
 
-        for node in self.nodes.into_iter() {
-            // add variant to container enum
-            // generate fully qualified variant
-            let Node { ident, generics, fields } = node.generate();
+/// The statements pushing the children held in `ty`, borrowed for `'a` by
+/// `place`, to `children` along with their name `name` and their `index`
+/// in the field
+fn children_named_nested(child_ident: &Ident, ty: &Type, place: TokenStream, name: &str) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! {
+            children.push((entish::path::ChildName { field: #name, index: Some(index) }, #place));
+            index += 1;
+        },
+        Holder::Nothing => quote! {},
+        Holder::Tuple(elems) => elems
+            .into_iter()
+            .enumerate()
+            .map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                children_named_nested(child_ident, elem, quote! { &(#place).#idx }, name)
+            })
+            .collect(),
+        Holder::Container(elem) => {
+            let inner = children_named_nested(child_ident, elem, quote! { __inner }, name);
+            quote! {
+                let _ = <#ty as entish::Map<&'a #elem, ()>>::map(#place, &mut |__inner: &'a #elem| { #inner });
+            }
+        }
+    }
+}
 
-            let fields_stream = match &fields {
-                Fields::Named(n) => quote! { #n },
-                Fields::Unnamed(un) => quote! { #un; },
-                Fields::Unit => quote! { ; }
+/// The statements pushing the children held in `fields` of a node, accessed
+/// as per `place`, to `children` along with their names, in the order of
+/// [Map](../entish/trait.Map.html). Unnamed fields are named by their index.
+fn children_named_stmts(child_ident: &Ident, fields: &Fields, place: Place) -> Vec<TokenStream> {
+    field_places(fields, place)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(idx, (place, field))| {
+            let ty = &field.ty;
+            let name = match &field.ident {
+                Some(ident) => ident.unraw().to_string(),
+                None => idx.to_string()
+            };
+            if is_ident(ty, child_ident) {
+                Some(quote! {
+                    children.push((entish::path::ChildName { field: #name, index: None }, &#place));
+                })
+            } else if contains_ident(ty, child_ident) {
+                let nested = children_named_nested(child_ident, ty, quote! { &#place }, &name);
+                Some(quote! {
+                    let mut index = 0;
+                    #nested
+                    let _ = index;
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The body of `children_named` given its statements
+fn children_named_body(stmts: Vec<TokenStream>) -> TokenStream {
+    if stmts.is_empty() {
+        return quote! { Vec::new() }
+    }
+    quote! {
+        {
+            let mut children = Vec::new();
+            #(#stmts)*
+            children
+        }
+    }
+}
+
+/// The methods giving access to the children of a node by name, given the
+/// body of `children_named`
+fn named_children_methods(child_ident: &Ident, children_named: TokenStream) -> TokenStream {
+    quote! {
+        /// My children, along with the names of the fields holding them
+        /// and their indices in these fields if they are containers, in
+        /// the order of `map`. Unnamed fields are named by their index.
+        pub fn children_named<'a>(&'a self) -> Vec<(entish::path::ChildName, &'a #child_ident)> {
+            #children_named
+        }
+
+        /// The child whose name is displayed as `name`, e.g. `left` or
+        /// `children[2]`, if there is one.
+        pub fn child_by_name(&self, name: &str) -> Option<&#child_ident> {
+            self.children_named()
+                .into_iter()
+                .find(|(child_name, _)| child_name.is(name))
+                .map(|(_, child)| child)
+        }
+
+        /// The names of the fields holding my children, once for each
+        /// child they hold, in the order of `map`.
+        pub fn child_fields(&self) -> Vec<&'static str> {
+            self.children_named().into_iter().map(|(name, _)| name.field).collect()
+        }
+    }
+}
+
+/// The variant of a fieldless `*Kind` enum for the node `ident`, with the
+/// match arms of its metadata given its `fields`
+fn kind_variant(child_ident: &Ident, kind_ident: &Ident, ident: &Ident, fields: &Fields) -> [TokenStream; 4] {
+    let names: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| match &field.ident {
+            Some(ident) => ident.unraw().to_string(),
+            None => idx.to_string()
+        })
+        .collect();
+    let child_names = fields
+        .iter()
+        .zip(names.iter())
+        .filter(|(field, _)| contains_ident(&field.ty, child_ident))
+        .map(|(_, name)| name);
+    // only known for nodes with a fixed number of children
+    let arity = match fields.iter().map(|field| fixed_arity(&field.ty, child_ident)).sum::<Option<usize>>() {
+        Some(arity) => quote! { Some(#arity) },
+        None => quote! { None }
+    };
+    let name = ident.unraw().to_string();
+
+    [
+        quote! { #kind_ident::#ident => #name },
+        quote! { #kind_ident::#ident => &[#(#names),*] },
+        quote! { #kind_ident::#ident => &[#(#child_names),*] },
+        quote! { #kind_ident::#ident => #arity }
+    ]
+}
+
+/// The parameter of `*Visit` for the type the children of type `param` are
+/// held as in the node of a tree, e.g. `Box<Tree>`, or the children of type
+/// `Self` without `param`
+fn held_param(param: Option<&Ident>) -> Ident {
+    match param {
+        Some(param) => format_ident!("{}{}", HELD_PARAM, param),
+        None => format_ident!("{}", HELD_PARAM)
+    }
+}
+
+/// `ty` as held in the node of a tree, with its children of type
+/// `child_ident` and of the types `sort_params` of the other trees of its
+/// family as the types they are held as, see [held_param]
+fn held_type(ty: &Type, child_ident: &Ident, sort_params: &[&Ident]) -> Type {
+    let held = held_param(None);
+    let ty = replace_child(ty, child_ident, quote! { #held });
+    sort_params.iter().fold(ty, |ty, param| {
+        let held = held_param(Some(param));
+        replace_child(&ty, param, quote! { #held })
+    })
+}
+
+/// The statements visiting the children held in `ty`, borrowed for `'tree`
+/// by `place`, in the default methods of `*Visit`
+fn visit_stmts(child_ident: &Ident, sort_params: &[&Ident], ty: &Type, place: TokenStream) -> TokenStream {
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { self.visit(&**#place); },
+        Holder::Nothing => quote! {},
+        Holder::Tuple(elems) => elems
+            .into_iter()
+            .enumerate()
+            .map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                visit_stmts(child_ident, sort_params, elem, quote! { &(#place).#idx })
+            })
+            .collect(),
+        Holder::Container(elem) => {
+            let elem_ty = held_type(elem, child_ident, sort_params);
+            let inner = visit_stmts(child_ident, sort_params, elem, quote! { __inner });
+            quote! { entish::ForEach::for_each(#place, &mut |__inner: &'tree #elem_ty| { #inner }); }
+        }
+    }
+}
+
+/// Push the bounds needed by [visit_stmts] on the containers of children in
+/// `ty`
+fn push_visit_bounds(child_ident: &Ident, sort_params: &[&Ident], ty: &Type, bounds: &mut Vec<TokenStream>) {
+    match holder(ty, child_ident) {
+        Holder::Child | Holder::Nothing => (),
+        Holder::Tuple(elems) => elems
+            .into_iter()
+            .for_each(|elem| push_visit_bounds(child_ident, sort_params, elem, bounds)),
+        Holder::Container(elem) => {
+            let ty = held_type(ty, child_ident, sort_params);
+            let elem_ty = held_type(elem, child_ident, sort_params);
+            bounds.push(quote! { #ty: entish::ForEach<#elem_ty> });
+            push_visit_bounds(child_ident, sort_params, elem, bounds)
+        }
+    }
+}
+
+/// The expression folding the children held in `ty`, as trees, moved out
+/// of `place`, in the default methods of `*Fold`
+fn fold_expr(child_ident: &Ident, ty: &Type, place: TokenStream) -> TokenStream {
+    let tree_param = format_ident!("{}", TREE_PARAM);
+    match holder(ty, child_ident) {
+        Holder::Child => quote! { self.fold(#place) },
+        Holder::Nothing => place,
+        Holder::Tuple(elems) => {
+            let items = elems.into_iter().enumerate().map(|(idx, elem)| {
+                let idx = Index::from(idx);
+                fold_expr(child_ident, elem, quote! { #place.#idx })
+            });
+            quote! { (#(#items,)*) }
+        },
+        Holder::Container(elem) => {
+            let elem_ty = replace_child(elem, child_ident, quote! { #tree_param });
+            let inner = fold_expr(child_ident, elem, quote! { __inner });
+            quote! { entish::MapOwned::map_owned(#place, &mut |__inner: #elem_ty| #inner) }
+        }
+    }
+}
+
+/// Push the bounds needed by [fold_expr] on the containers of children in `ty`
+fn push_fold_bounds(child_ident: &Ident, ty: &Type, bounds: &mut Vec<TokenStream>) {
+    let tree_param = format_ident!("{}", TREE_PARAM);
+    match holder(ty, child_ident) {
+        Holder::Child | Holder::Nothing => (),
+        Holder::Tuple(elems) => elems.into_iter().for_each(|elem| push_fold_bounds(child_ident, elem, bounds)),
+        Holder::Container(elem) => {
+            let ty = replace_child(ty, child_ident, quote! { #tree_param });
+            let elem_ty = replace_child(elem, child_ident, quote! { #tree_param });
+            bounds.push(quote! { #ty: entish::MapOwned<#elem_ty, #elem_ty, OuterO = #ty> });
+            push_fold_bounds(child_ident, elem, bounds)
+        }
+    }
+}
+
+/// What the node `ident` adds to the `*Visit` and `*Fold` traits
+struct VisitFold {
+    /// The doc, signature and body of the visit method, which needs the
+    /// bounds of all nodes as it visits children with `visit`
+    visit_method: (String, TokenStream, TokenStream),
+    /// The arm of `visit` dispatching to `visit_method`
+    visit_arm: TokenStream,
+    /// The doc, signature and body of the fold method, which needs the
+    /// bounds of all nodes as it folds children with `fold`
+    fold_method: (String, TokenStream, TokenStream),
+    /// The arm of `fold` dispatching to the fold method
+    fold_arm: TokenStream,
+    /// The bounds needed to visit the containers of children
+    visit_bounds: Vec<TokenStream>,
+    /// The bounds needed to fold the containers of children
+    fold_bounds: Vec<TokenStream>
+}
+
+fn visit_fold(
+    child_ident: &Ident,
+    sort_params: &[&Ident],
+    path: &TokenStream,
+    ident: &Ident,
+    fields: &Fields,
+    variants_as_structs: bool
+) -> VisitFold {
+    let tree_param = format_ident!("{}", TREE_PARAM);
+    let snake = snake_name(ident);
+    let visit_ident = format_ident!("visit_{}", snake);
+    let fold_ident = format_ident!("fold_{}", snake);
+
+    let mut to_tree = ReplaceType::replace_with(child_ident.clone(), syn::parse2(quote! { #tree_param }).unwrap());
+
+    let places = field_places(fields, Place::Param);
+    let params: Vec<&TokenStream> = places.iter().map(|(place, _)| place).collect();
+    let visit_params: Vec<TokenStream> = places
+        .iter()
+        .map(|(place, field)| {
+            let ty = held_type(&field.ty, child_ident, sort_params);
+            quote! { #place: &'tree #ty }
+        })
+        .collect();
+    let fold_params: Vec<TokenStream> = places
+        .iter()
+        .map(|(place, field)| {
+            let ty = to_tree.fold_type(field.ty.clone());
+            quote! { #place: #ty }
+        })
+        .collect();
+
+    let visit_stmts = places.iter().map(|(place, field)| {
+        if contains_ident(&field.ty, child_ident) {
+            visit_stmts(child_ident, sort_params, &field.ty, place.clone())
+        } else {
+            quote! { let _ = #place; }
+        }
+    });
+
+    let mut visit_bounds = Vec::new();
+    let mut fold_bounds = Vec::new();
+    for (_, field) in places.iter() {
+        push_visit_bounds(child_ident, sort_params, &field.ty, &mut visit_bounds);
+        push_fold_bounds(child_ident, &field.ty, &mut fold_bounds);
+    }
+    let folded_fields = map_fields(fields, Place::Param, |place, field| {
+        fold_expr(child_ident, &field.ty, place.clone())
+    });
+
+    let pattern = destructure_params(fields);
+    let (pattern, folded) = if variants_as_structs {
+        (
+            quote! { #path(#ident #pattern) },
+            quote! { #path(#ident #folded_fields) }
+        )
+    } else {
+        (quote! { #path #pattern }, quote! { #path #folded_fields })
+    };
+
+    let visit_doc = format!(
+        "Visit a node of type `{}` given its borrowed fields. By default, visits its children.",
+        ident
+    );
+    let fold_doc = format!(
+        "Fold a node of type `{}` given its fields. By default, folds its children and rebuilds the node.",
+        ident
+    );
+
+    VisitFold {
+        visit_method: (
+            visit_doc,
+            quote! { fn #visit_ident(&mut self, #(#visit_params),*) },
+            quote! { #(#visit_stmts)* }
+        ),
+        visit_arm: quote! { #pattern => self.#visit_ident(#(#params),*) },
+        fold_method: (
+            fold_doc,
+            quote! { fn #fold_ident(&mut self, #(#fold_params),*) -> #tree_param },
+            quote! { #tree_param::from(#folded) }
+        ),
+        fold_arm: quote! { #pattern => self.#fold_ident(#(#params),*) },
+        visit_bounds,
+        fold_bounds
+    }
+}
+
+/// The arguments matching `generics` when used in a type, e.g. `'a, T` for
+/// `<'a, T: Clone>`
+fn generic_args(generics: &Generics) -> Vec<TokenStream> {
+    generics.params
+        .iter()
+        .map(|gp| match gp {
+            GenericParam::Type(TypeParam { ident, .. }) => quote! { #ident },
+            GenericParam::Lifetime(LifetimeDef { lifetime, .. }) => quote! { #lifetime },
+            GenericParam::Const(ConstParam { ident, .. }) => quote! { #ident }
+        })
+        .collect()
+}
+
+/// A constructor on the tree wrapper type for the node `ident`, taking the
+/// fields of the node with children as trees.
+fn constructor(
+    path: &TokenStream,
+    ident: &Ident,
+    fields: &Fields,
+    variants_as_structs: bool
+) -> TokenStream {
+    let mut ri = ReplaceIdent::replace_with(
+        format_ident!("{}", CHILD),
+        format_ident!("{}", SELF)
+    );
+    let fields = ri.fold_fields(fields.clone());
+
+    let (params, args) = match &fields {
+        Fields::Named(FieldsNamed { named, .. }) => {
+            let idents: Vec<_> = named.iter().map(|field| &field.ident).collect();
+            let tys = named.iter().map(|field| &field.ty);
+            (quote! { #(#idents: #tys),* }, quote! { { #(#idents),* } })
+        },
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
+            let idents: Vec<_> = (0..unnamed.len()).map(|idx| format_ident!("arg{}", idx)).collect();
+            let tys = unnamed.iter().map(|field| &field.ty);
+            (quote! { #(#idents: #tys),* }, quote! { ( #(#idents),* ) })
+        },
+        Fields::Unit => (quote! {}, quote! {})
+    };
+
+    let node = if variants_as_structs {
+        quote! { #path(#ident #args) }
+    } else {
+        quote! { #path #args }
+    };
+
+    let ctor_ident = snake_case(ident);
+    let doc = format!("Make a tree whose root is a node of type `{}`", ident);
+    quote! {
+        #[doc = #doc]
+        pub fn #ctor_ident(#params) -> Self {
+            Self::new(#node)
+        }
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Clone, Copy)]
+enum SupportedDerives {
+    TryInto,
+    From,
+    Map,
+    MapOwned,
+    MapMut,
+    Traverse,
+    Zip,
+    IntoResult,
+    IntoOption,
+    IntoValidated
+}
+
+impl SupportedDerives {
+    /// `Ok(None)` if `p` is a derive entish does not know about and should
+    /// be kept as is. Paths prefixed with `entish::` must be supported.
+    fn try_from(p: &Path) -> syn::Result<Option<Self>> {
+        let is_entish_path = p.leading_colon.is_none() &&
+            p.segments.len() == 2 &&
+            p.segments[0].ident == "entish";
+        let ident = match p.get_ident() {
+            Some(ident) => ident,
+            None if is_entish_path => &p.segments[1].ident,
+            None => return Ok(None)
+        };
+        let derive = match ident.to_string().as_str() {
+            "TryInto" => Some(Self::TryInto),
+            "From" => Some(Self::From),
+            "Map" => Some(Self::Map),
+            "MapOwned" => Some(Self::MapOwned),
+            "MapMut" => Some(Self::MapMut),
+            "Traverse" => Some(Self::Traverse),
+            "Zip" => Some(Self::Zip),
+            "IntoResult" => Some(Self::IntoResult),
+            "IntoOption" => Some(Self::IntoOption),
+            "IntoValidated" => Some(Self::IntoValidated),
+            _ => None
+        };
+        match derive {
+            None if is_entish_path => Err(Error::new_spanned(p, "unknown entish derive")),
+            derive => Ok(derive)
+        }
+    }
+
+    /// Whether this can only be derived with `#[entish(variants_as_structs)]`
+    fn needs_variants_as_structs(&self) -> bool {
+        matches!(self, Self::TryInto | Self::From)
+    }
+}
+
+#[derive(Debug)]
+pub struct NodeBuilder {
+    generics: Generics,
+    variant: Variant,
+    relevant: MentionedGenerics,
+}
+
+pub struct Node {
+    ident: Ident,
+    attrs: Vec<Attribute>,
+    generics: Generics,
+    fields: Fields
+}
+
+impl NodeBuilder {
+    pub fn from_variant(generics: &Generics, variant: &Variant) -> Self {
+        Self {
+            generics: generics.clone(),
+            variant: variant.clone(),
+            relevant: MentionedGenerics::default()
+        }
+    }
+
+    pub fn extend_closure<'a, I: IntoIterator<Item = &'a Ident>>(
+        &mut self,
+        i: I
+    ) -> &mut Self {
+        self.relevant.targets.extend(i.into_iter().cloned());
+        self
+    }
+
+    pub fn generate(mut self) -> Node {
+        syn::fold::fold_variant(&mut self.relevant, self.variant.clone());
+        let closure = self.relevant.into_mentioned();
+
+        let ident = self.variant.ident;
+
+        let generics: Generics = {
+            let params = self.generics
+                .params
+                .into_iter()
+                .filter_map(|p| match p {
+                    GenericParam::Type(TypeParam { ident, .. }) if closure.contains(&ident) => {
+                        Some(generic_param!(ident))
+                    },
+                    _ => None
+                })
+                .collect();
+            Generics {
+                params,
+                ..Default::default()
+            }
+        };
+
+        Node { ident, attrs: self.variant.attrs, generics, fields: self.variant.fields }
+    }
+}
+
+/// The arms of the `match` in the methods of the container enum mapping its
+/// children of the type of a sort, one per node
+#[derive(Default)]
+struct SortArms {
+    map: Vec<TokenStream>,
+    map_owned: Vec<TokenStream>,
+    map_mut: Vec<TokenStream>,
+    try_traverse: Vec<TokenStream>
+}
+
+/// The bodies of the methods of [sort_methods]
+struct SortBodies {
+    /// Builds the mapped node out of `&self`
+    map: TokenStream,
+    /// Builds the mapped node out of `self`
+    map_owned: TokenStream,
+    /// Applies `f` to the children in `&mut self`
+    map_mut: TokenStream,
+    /// Builds the traversed node out of `self`, returning early on errors
+    try_traverse: TokenStream
+}
+
+/// The methods of the node `ident` mapping its children of the type of
+/// `sort`, and only these, for each of `Map`, `MapOwned`, `MapMut` and
+/// `Traverse` in `derives`. Its other children are cloned along with its
+/// `fields` by `map_*`, and kept as they are by the others.
+fn sort_methods<'a, I>(
+    sort: &Sort,
+    ident: &Ident,
+    generics: &Generics,
+    fields: I,
+    derives: &HashSet<SupportedDerives>,
+    bodies: SortBodies
+) -> TokenStream
+where
+    I: Iterator<Item = &'a Field>
+{
+    let map_output_ident = format_ident!("{}", MAP_OUTPUT);
+    let param = &sort.param;
+    let mapped_generics = ReplaceIdent::replace_with(param.clone(), map_output_ident.clone())
+        .fold_generics(generics.clone());
+    let SortBodies { map, map_owned, map_mut, try_traverse } = bodies;
+
+    let mut out = TokenStream::new();
+    if derives.contains(&SupportedDerives::Map) {
+        let method = sort.method_ident("map");
+        let doc = format!(
+            "Map my children of type `{}` with `f`, borrowing them, and clone my other fields",
+            sort.ident
+        );
+        let clone_predicates = clone_predicates(generics, fields, &[param]);
+        out.extend(quote! {
+            #[doc = #doc]
+            pub fn #method<'a, #map_output_ident: 'a, F>(&'a self, f: &mut F) -> #ident#mapped_generics
+            where
+                F: FnMut(&'a #param) -> #map_output_ident,
+                #clone_predicates
+            {
+                #map
+            }
+        });
+    }
+    if derives.contains(&SupportedDerives::MapOwned) {
+        let method = sort.method_ident("map_owned");
+        let doc = format!(
+            "Map my children of type `{}` with `f`, moving them out of me, and leave the others as they are",
+            sort.ident
+        );
+        out.extend(quote! {
+            #[doc = #doc]
+            pub fn #method<#map_output_ident, F>(self, f: &mut F) -> #ident#mapped_generics
+            where
+                F: FnMut(#param) -> #map_output_ident
+            {
+                #map_owned
+            }
+        });
+    }
+    if derives.contains(&SupportedDerives::MapMut) {
+        let method = sort.method_ident("map_mut");
+        let doc = format!("Apply `f` to my children of type `{}` in place", sort.ident);
+        out.extend(quote! {
+            #[doc = #doc]
+            pub fn #method<'a, F>(&'a mut self, f: &mut F)
+            where
+                F: FnMut(&'a mut #param),
+                #param: 'a
+            {
+                #map_mut
+            }
+        });
+    }
+    if derives.contains(&SupportedDerives::Traverse) {
+        let try_method = sort.method_ident("try_traverse");
+        let method = sort.method_ident("traverse");
+        let try_doc = format!(
+            "Map my children of type `{}` with `f` like `{}`, stopping at the first error",
+            sort.ident,
+            sort.method_ident("map_owned")
+        );
+        let doc = format!(
+            "Like `{}` but stopping at the first child `f` gives no output for, \
+             for any `entish::Effect`",
+            try_method
+        );
+        out.extend(quote! {
+            #[doc = #try_doc]
+            pub fn #try_method<#map_output_ident, __Error, F>(self, f: &mut F)
+                -> std::result::Result<#ident#mapped_generics, __Error>
+            where
+                F: FnMut(#param) -> std::result::Result<#map_output_ident, __Error>
+            {
+                Ok(#try_traverse)
+            }
+
+            #[doc = #doc]
+            pub fn #method<#map_output_ident, R, F>(self, f: &mut F) -> R::With<#ident#mapped_generics>
+            where
+                R: entish::Effect<#map_output_ident>,
+                F: FnMut(#param) -> R
+            {
+                match self.#try_method(&mut |child| entish::Effect::branch(f(child))) {
+                    Ok(output) => entish::Effect::from_output(output),
+                    Err(residual) => entish::Effect::from_residual(residual)
+                }
+            }
+        });
+    }
+    out
+}
+
+/// The method of the node `ident` borrowing its children of all the types
+/// `idents`, i.e. of its own type and of the other trees of its family,
+/// whose `body` builds the borrowed node out of `fields`.
+fn as_refs_method<'a, I>(
+    ident: &Ident,
+    generics: &Generics,
+    fields: I,
+    idents: &[&Ident],
+    body: TokenStream
+) -> TokenStream
+where
+    I: Iterator<Item = &'a Field>
+{
+    let args = generics.params.iter().zip(generic_args(generics)).map(|(gp, arg)| match gp {
+        GenericParam::Type(TypeParam { ident, .. }) if idents.contains(&ident) => quote! { &'a #ident },
+        _ => arg
+    });
+    let where_clause = where_clause(clone_predicates(generics, fields, idents));
+    quote! {
+        /// Borrow my children of all types, and clone my other fields
+        pub fn as_refs<'a>(&'a self) -> #ident<#(#args),*>
+        #where_clause
+        {
+            #body
+        }
+    }
+}
+
+/// The fields of all the variants of an enum, or of a struct
+fn data_fields(data: &Data) -> Box<dyn Iterator<Item = &Field> + '_> {
+    match data {
+        Data::Enum(DataEnum { variants, .. }) => Box::new(variants.iter().flat_map(|variant| variant.fields.iter())),
+        Data::Struct(DataStruct { fields, .. }) => Box::new(fields.iter()),
+        Data::Union(DataUnion { fields, .. }) => Box::new(fields.named.iter())
+    }
+}
+
+/// A tree declared along with others in the same `entish!` block, whose
+/// nodes can have children of each other's types.
+#[derive(Debug, Clone)]
+pub struct Sort {
+    ident: Ident,
+    /// The type parameter replacing children of this type in the nodes of
+    /// the other trees
+    param: Ident,
+    tree: Option<Ident>,
+    /// The indirection between its nodes and their children
+    indirection: Path,
+    /// The other trees of the family whose nodes its nodes have as children
+    mentions: Vec<Ident>
+}
+
+impl Sort {
+    /// The `method` mapping the children of this type in the nodes of the
+    /// other trees, e.g. `map_owned_stmt` for `map_owned`
+    fn method_ident(&self, method: &str) -> Ident {
+        format_ident!("{}_{}", method, snake_name(&self.ident))
+    }
+}
+
+/// The family of trees declared by `inputs`, whose builders are `builders`,
+/// which is empty if there is a single one.
+pub fn family(inputs: &[DeriveInput], builders: &[EntishBuilder]) -> syn::Result<Vec<Sort>> {
+    if inputs.len() < 2 {
+        return Ok(Vec::new())
+    }
+
+    let mut seen = HashSet::new();
+    collect_errors(inputs.iter().map(|input| {
+        if seen.insert(input.ident.to_string()) {
+            Ok(())
+        } else {
+            Err(Error::new_spanned(&input.ident, "a tree of this name is already declared in this block"))
+        }
+    }))?;
+
+    // children of other types are stored as their trees, so that all of them
+    // or none must be declared
+    if builders.iter().any(|builder| builder.tree.is_some()) {
+        collect_errors(builders.iter().map(|builder| match &builder.tree {
+            Some(_) => Ok(()),
+            None => Err(Error::new_spanned(
+                &builder.ident,
+                "the other trees of this block declare `tree = \"..\"`, so this one must too"
+            ))
+        }))?;
+    }
+
+    Ok(inputs.iter().zip(builders.iter()).map(|(input, builder)| {
+        let mentions = inputs
+            .iter()
+            .map(|other| &other.ident)
+            .filter(|other| **other != input.ident)
+            .filter(|other| data_fields(&input.data).any(|field| contains_ident(&field.ty, other)))
+            .cloned()
+            .collect();
+        Sort {
+            ident: input.ident.clone(),
+            param: format_ident!("{}{}", input.ident, CHILD),
+            tree: builder.tree.clone(),
+            indirection: builder.indirection.clone().unwrap_or_else(|| syn::parse2(quote! { Box }).unwrap()),
+            mentions
+        }
+    }).collect())
+}
+
+#[derive(Debug)]
+pub struct EntishBuilder {
+    ident: Ident,
+    attributes: Vec<Attribute>,
+    generics: Generics,
+    nodes: Vec<NodeBuilder>,
+    variants_as_structs: bool,
+    tree: Option<Ident>,
+    indirection: Option<Path>,
+    tree_derives: Vec<Ident>,
+    /// Whether the tree is declared by a struct, as its single node
+    is_struct: bool,
+    /// The trees declared in the same block, if any
+    family: Vec<Sort>,
+    /// The other trees of the family whose nodes are children of its nodes
+    sorts: Vec<Sort>
+}
+
+impl EntishBuilder {
+    pub fn new(input: &DeriveInput) -> syn::Result<Self> {
+
+        let ident = input.ident.clone();
+
+        let mut generics = input.generics.clone();
+        generics.params.push(generic_param!(format_ident!("{}", CHILD)));
+
+        let mut attributes = Vec::new();
+        let mut variants_as_structs = false;
+        let mut tree = None;
+        let mut indirection = None;
+        let mut tree_derives = Vec::new();
+
+        for attribute in input.attrs.iter() {
+            if !attribute.path.is_ident("entish") {
+                attributes.push(attribute.clone());
+                continue
+            }
+
+            let nested = match attribute.parse_meta()? {
+                Meta::List(MetaList { nested, .. }) => nested,
+                meta => {
+                    return Err(Error::new_spanned(meta, "expected a list of options as in `#[entish(..)]`"))
+                }
+            };
+            collect_errors(nested.iter().map(|nested_meta| {
+                match nested_meta {
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("variants_as_structs") => {
+                        variants_as_structs = true;
+                        Ok(())
+                    },
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit: Lit::Str(lit), .. }))
+                        if path.is_ident("tree") =>
+                    {
+                        tree = Some(lit.parse()?);
+                        Ok(())
+                    },
+                    NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit: Lit::Str(lit), .. }))
+                        if path.is_ident("indirection") =>
+                    {
+                        indirection = Some(lit.parse()?);
+                        Ok(())
+                    },
+                    NestedMeta::Meta(Meta::List(MetaList { path, nested, .. })) if path.is_ident("derive_tree") => {
+                        collect_errors(nested.iter().map(|nested_meta| match nested_meta {
+                            NestedMeta::Meta(Meta::Path(p)) if TREE_DERIVES.iter().any(|d| p.is_ident(d)) => {
+                                tree_derives.push(p.get_ident().unwrap().clone());
+                                Ok(())
+                            },
+                            _ => Err(Error::new_spanned(
+                                nested_meta,
+                                format!("expected one of {}", TREE_DERIVES.join(", "))
+                            ))
+                        }))
+                    },
+                    _ => Err(Error::new_spanned(nested_meta, "unsupported option in `#[entish(..)]`"))
+                }
+            }))?;
+        }
+
+        Ok(Self {
+            ident,
+            attributes,
+            generics,
+            nodes: Vec::new(),
+            variants_as_structs,
+            tree,
+            indirection,
+            tree_derives,
+            is_struct: false,
+            family: Vec::new(),
+            sorts: Vec::new()
+        })
+    }
+
+    /// Join the `family` of trees declared in the same block, adding a type
+    /// parameter, just before `Child`, for each other tree whose nodes are
+    /// children of its nodes.
+    pub fn join_family(&mut self, family: &[Sort]) -> syn::Result<&mut Self> {
+        let mentions = match family.iter().find(|sort| sort.ident == self.ident) {
+            Some(sort) => &sort.mentions,
+            None => return Ok(self)
+        };
+        self.family = family.to_vec();
+        self.sorts = family
+            .iter()
+            .filter(|sort| mentions.contains(&sort.ident))
+            .cloned()
+            .collect();
+
+        // the `*Tree` traits borrow children of other types through
+        // associated types, which the parameters of the trait would have to
+        // outlive
+        if self.generics.params.len() > 1 {
+            return Err(Error::new_spanned(
+                &self.generics,
+                "the trees of a family cannot have generic parameters"
+            ))
+        }
+
+        for sort in self.sorts.iter() {
+            let idx = self.generics.params.len() - 1;
+            self.generics.params.insert(idx, generic_param!(sort.param.clone()));
+        }
+
+        if !self.sorts.is_empty() {
+            if let Some(tree_derive) = self.tree_derives.first() {
+                return Err(Error::new_spanned(
+                    tree_derive,
+                    "`derive_tree` is not supported by trees with children of other types"
+                ))
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn get_generic_idents(&self) -> impl Iterator<Item = &'_ Ident> + '_ {
+        self.generics
+            .params
+            .iter()
+            .filter_map(|gp| {
+                match gp {
+                    GenericParam::Type(TypeParam { ident, .. }) => {
+                        Some(ident)
+                    },
+                    _ => None
+                }
+            })
+    }
+
+    pub fn add_node(&mut self, variant: &Variant) -> syn::Result<&mut Self> {
+        let child_ident = format_ident!("{}", CHILD);
+
+        let mut ri = ReplaceIdent::replace_with(
+            format_ident!("{}", SELF),
+            child_ident.clone()
+        );
+
+        let mut variant = ri.fold_variant(variant.clone());
+        for sort in self.sorts.iter() {
+            let param = &sort.param;
+            let mut rt = ReplaceType::replace_with(sort.ident.clone(), syn::parse2(quote! { #param }).unwrap());
+            variant = rt.fold_variant(variant);
+        }
+        let idents: Vec<&Ident> = std::iter::once(&child_ident)
+            .chain(self.sorts.iter().map(|sort| &sort.param))
+            .collect();
+        collect_errors(variant.fields.iter().map(|field| check_child_type(&field.ty, &idents)))?;
+
+        let mut node = NodeBuilder::from_variant(&self.generics, &variant);
+
+        node.extend_closure(self.get_generic_idents());
+
+        self.nodes.push(node);
+        Ok(self)
+    }
+
+    /// Add the struct declaring the tree as its single node, with `fields`.
+    pub fn add_struct(&mut self, fields: &Fields) -> syn::Result<&mut Self> {
+        if self.variants_as_structs {
+            return Err(Error::new_spanned(&self.ident, "`variants_as_structs` only applies to enums"))
+        }
+        let self_ident = format_ident!("{}", SELF);
+        if !fields.iter().any(|field| contains_ident(&field.ty, &self_ident)) {
+            return Err(Error::new_spanned(&self.ident, "a struct must have children of type `Self` to be a tree"))
+        }
+        self.is_struct = true;
+        self.add_node(&Variant {
+            attrs: Vec::new(),
+            ident: self.ident.clone(),
+            fields: fields.clone(),
+            discriminant: None
+        })
+    }
+
+    pub fn generate(self) -> syn::Result<TokenStream> {
+        let mut out = TokenStream::new();
+
+        let child_ident = format_ident!("{}", CHILD);
+
+        let c_ident = self.ident;
+        let c_generics = self.generics;
+
+        let mut metas = Vec::new();
+        let mut derives = HashSet::new();
+
+        let trait_ident = format_ident!("{}Tree", c_ident);
+
+        let variants_as_structs = self.variants_as_structs;
+        let is_struct = self.is_struct;
+        let tree = self.tree;
+        let indirection = self.indirection
+            .unwrap_or_else(|| syn::parse2(quote! { Box }).unwrap());
+        let family = self.family;
+        let sorts = self.sorts;
+        // the types of the children of the nodes, of their own type and of
+        // the other trees of the family
+        let child_idents: Vec<&Ident> = std::iter::once(&child_ident)
+            .chain(sorts.iter().map(|sort| &sort.param))
+            .collect();
+
+        // sift through attributes; remove all paths in #[derive(..)]
+        // if path is supported
+        for attribute in self.attributes.into_iter() {
+            let mut meta = attribute.parse_meta()?;
+            if let Meta::List(MetaList { path, nested, .. }) = &mut meta {
+                if path.is_ident("derive") {
+                    let mut kept = Punctuated::new();
+                    collect_errors(nested.iter().map(|nested_meta| match nested_meta {
+                        NestedMeta::Meta(Meta::Path(p)) => {
+                            match SupportedDerives::try_from(p)? {
+                                Some(derive) if derive.needs_variants_as_structs() && !variants_as_structs => {
+                                    Err(Error::new_spanned(
+                                        p,
+                                        "this can only be derived with `#[entish(variants_as_structs)]`"
+                                    ))
+                                },
+                                Some(derive) => {
+                                    derives.insert(derive);
+                                    Ok(())
+                                },
+                                None => {
+                                    kept.push(nested_meta.clone());
+                                    Ok(())
+                                }
+                            }
+                        },
+                        _ => Err(Error::new_spanned(nested_meta, "only paths allowed here"))
+                    }))?;
+                    *nested = kept;
+                }
+            }
+            metas.push(meta);
+        }
+
+        // std derives on the node, which give the same traits on its shape
+        let node_derives: HashSet<String> = metas.iter()
+            .filter_map(|meta| match meta {
+                Meta::List(MetaList { path, nested, .. }) if path.is_ident("derive") => Some(nested),
+                _ => None
+            })
+            .flatten()
+            .filter_map(|nested_meta| match nested_meta {
+                NestedMeta::Meta(Meta::Path(p)) => p.segments.last().map(|s| s.ident.to_string()),
+                _ => None
+            })
+            .collect();
+
+        let tree_derives = self.tree_derives;
+        if let Some(tree_derive) = tree_derives.first() {
+            if tree.is_none() {
+                return Err(Error::new_spanned(tree_derive, "`derive_tree` requires `tree = \"..\"`"))
+            }
+        }
+        collect_errors(tree_derives.iter().map(|tree_derive| {
+            // each is implemented with the same trait on the shapes of nodes
+            if node_derives.contains(&tree_derive.to_string()) {
+                Ok(())
+            } else {
+                Err(Error::new_spanned(
+                    tree_derive,
+                    format!("this requires `#[derive({})]` on the enum", tree_derive)
+                ))
+            }
+        }))?;
+
+        // derives not consumed by entish also apply to the tree wrapper,
+        // unless implemented with `derive_tree`
+        let derive_attributes: Vec<TokenStream> = metas.iter()
+            .filter(|meta| meta.path().is_ident("derive"))
+            .map(|meta| {
+                let mut meta = meta.clone();
+                if let Meta::List(MetaList { nested, .. }) = &mut meta {
+                    *nested = nested
+                        .iter()
+                        .filter(|nested_meta| match nested_meta {
+                            NestedMeta::Meta(Meta::Path(p)) => {
+                                !tree_derives.iter().any(|tree_derive| {
+                                    p.segments.last().is_some_and(|s| &s.ident == tree_derive)
+                                })
+                            },
+                            _ => true
+                        })
+                        .cloned()
+                        .collect();
+                }
+                quote! { #[#meta] }
+            })
+            .collect();
+
+        let attributes: TokenStream = metas.into_iter()
+            .map(|meta| quote! { #[#meta] })
+            .collect();
+
+        let mut variants = Punctuated::<Variant, Comma>::new();
+
+        // arms of the `match` in the impls on the container enum
+        let mut map_arms = Vec::new();
+        let mut map_owned_arms = Vec::new();
+        // arms of `map_*`, `map_owned_*`, etc. for each other tree of the
+        // family
+        let mut sort_arms: Vec<SortArms> = sorts.iter().map(|_| SortArms::default()).collect();
+        let mut map_mut_arms = Vec::new();
+        let mut traverse_arms = Vec::new();
+        let mut zip_arms = Vec::new();
+        let mut into_result_arms = Vec::new();
+        let mut into_option_arms = Vec::new();
+        let mut into_validated_arms = Vec::new();
+        let mut children_named_arms = Vec::new();
+        let mut kind_arms = Vec::new();
+        // arms of `as_refs`, in a family
+        let mut as_refs_arms = Vec::new();
+
+        // variants of the `*Kind` enum, and arms of its `name`, `fields`,
+        // `child_fields` and `arity`
+        let kind_ident = format_ident!("{}Kind", c_ident);
+        let mut kinds = Vec::new();
+        let mut kind_metadata_arms = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+
+        // methods and arms of the `*Visit` and `*Fold` traits
+        let mut visit_folds = Vec::new();
+
+        // constructors on the tree wrapper type, if any
+        let mut constructors = Vec::new();
+
+        // fields of all nodes
+        let mut all_fields = Vec::new();
+
+        for node in self.nodes.into_iter() {
+            let Node { ident, attrs, generics, fields } = node.generate();
+            all_fields.extend(fields.iter().cloned());
+
+            // the node is a variant of the enum, or the struct itself
+            let (path, self_path) = if is_struct {
+                (quote! { #c_ident }, quote! { Self })
+            } else {
+                (quote! { #c_ident::#ident }, quote! { Self::#ident })
+            };
+
+            kind_arms.push(quote! { #self_path { .. } => #kind_ident::#ident });
+            let metadata_arms = kind_variant(&child_ident, &kind_ident, &ident, &fields);
+            for (arms, arm) in kind_metadata_arms.iter_mut().zip(metadata_arms.iter()) {
+                arms.push(arm.clone());
+            }
+            kinds.push(ident.clone());
+
+            visit_folds.push(visit_fold(&child_ident, &child_idents[1..], &path, &ident, &fields, variants_as_structs));
+
+            if let Some(tree) = &tree {
+                if snake_name(&ident) == "new" {
+                    return Err(Error::new_spanned(
+                        &ident,
+                        format!("the constructor of this node would conflict with `{}::new`", tree)
+                    ))
+                }
+                // children of other types are taken as their trees
+                let fields = sorts.iter().fold(fields.clone(), |fields, sort| {
+                    let tree = sort.tree.clone().expect("checked by `family`");
+                    ReplaceIdent::replace_with(sort.param.clone(), tree).fold_fields(fields)
+                });
+                constructors.push(constructor(&path, &ident, &fields, variants_as_structs));
+            }
+
+            if !variants_as_structs {
+                // keep the variant as is and match on its fields directly
+                let ref_pattern = destructure_fields(&fields, true);
+                let pattern = destructure_fields(&fields, false);
+
+                let mapped_fields = map_fields(&fields, Place::RefBinding, |place, field| {
+                    map_field(&child_ident, place, field)
+                });
+                map_arms.push(quote! {
+                    #self_path #ref_pattern => #path #mapped_fields
+                });
+
+                let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                    map_owned_field(&child_ident, place, field)
+                });
+                map_owned_arms.push(quote! {
+                    #self_path #pattern => #path #mapped_fields
+                });
+
+                for (sort, arms) in sorts.iter().zip(sort_arms.iter_mut()) {
+                    let mapped_fields = map_fields(&fields, Place::RefBinding, |place, field| {
+                        map_field(&sort.param, place, field)
+                    });
+                    arms.map.push(quote! {
+                        #self_path #ref_pattern => #path #mapped_fields
+                    });
+
+                    let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                        map_owned_field(&sort.param, place, field)
+                    });
+                    arms.map_owned.push(quote! {
+                        #self_path #pattern => #path #mapped_fields
+                    });
+
+                    let map_mut_stmts = field_places(&fields, Place::Binding)
+                        .into_iter()
+                        .map(|(place, field)| map_mut_field(&sort.param, &place, field));
+                    arms.map_mut.push(quote! {
+                        #self_path #pattern => { #(#map_mut_stmts)* }
+                    });
+
+                    let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                        traverse_field(&sort.param, place, field)
+                    });
+                    arms.try_traverse.push(quote! {
+                        #self_path #pattern => #path #mapped_fields
+                    });
+                }
+
+                let map_mut_stmts = field_places(&fields, Place::Binding)
+                    .into_iter()
+                    .map(|(place, field)| map_mut_field(&child_ident, &place, field));
+                map_mut_arms.push(quote! {
+                    #self_path #pattern => { #(#map_mut_stmts)* }
+                });
+
+                let other_pattern = destructure_other_fields(&fields);
+                let zipped_fields = zip_fields(&fields, Place::RefBinding, Place::OtherRefBinding, |place, other_place, field| {
+                    zip_field(&child_ident, place, other_place, field)
+                });
+                zip_arms.push(quote! {
+                    (&#self_path #ref_pattern, &#path #other_pattern) => {
+                        #path #zipped_fields
+                    }
+                });
+
+                let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                    traverse_field(&child_ident, place, field)
+                });
+                traverse_arms.push(quote! {
+                    #self_path #pattern => #path #mapped_fields
+                });
+
+                let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                    into_result_field(&child_ident, place, field)
+                });
+                into_result_arms.push(quote! {
+                    #self_path #pattern => #path #mapped_fields
+                });
+
+                let mapped_fields = map_fields(&fields, Place::Binding, |place, field| {
+                    into_option_field(&child_ident, place, field)
+                });
+                into_option_arms.push(quote! {
+                    #self_path #pattern => #path #mapped_fields
+                });
+
+                let validated = validated_node(&child_ident, &path, &fields);
+                into_validated_arms.push(quote! {
+                    #self_path #pattern => #validated
+                });
+
+                let children_named = children_named_body(
+                    children_named_stmts(&child_ident, &fields, Place::RefBinding)
+                );
+                children_named_arms.push(quote! {
+                    #self_path #ref_pattern => #children_named
+                });
+
+                let borrowed_fields = map_fields(&fields, Place::RefBinding, |place, field| {
+                    as_refs_expr(&child_idents, &field.ty, quote! { &#place })
+                });
+                as_refs_arms.push(quote! {
+                    #self_path #ref_pattern => #path #borrowed_fields
+                });
+
+                variants.push(Variant { attrs, ident, fields, discriminant: None });
+                continue
+            }
+
+            // add variant to container enum
+            // generate fully qualified variant
+            let fields_stream = match &fields {
+                Fields::Named(n) => quote! { #n },
+                Fields::Unnamed(un) => quote! { #un; },
+                Fields::Unit => quote! { ; }
             };
 
             variants.push(syn::parse2(quote! { #ident(#ident#generics) }).unwrap());
 
-            let ident_doc = format!(
-                "A node of type `{ident}` in a [{trait_}](trait.{trait_}.html)",
-                ident = ident,
-                trait_ = trait_ident
-            );
-            out.extend(quote! {
-                #[doc = #ident_doc]
-                #attributes
-                pub struct #ident#generics #fields_stream
-            });
+            map_arms.push(quote! {
+                Self::#ident(ref variant) => #c_ident::#ident(variant.map(f))
+            });
+            map_owned_arms.push(quote! {
+                Self::#ident(variant) => #c_ident::#ident(variant.map_owned(f))
+            });
+            for (sort, arms) in sorts.iter().zip(sort_arms.iter_mut()) {
+                if generics.type_params().any(|TypeParam { ident, .. }| *ident == sort.param) {
+                    let map_sort = sort.method_ident("map");
+                    let map_owned_sort = sort.method_ident("map_owned");
+                    let map_mut_sort = sort.method_ident("map_mut");
+                    let try_traverse_sort = sort.method_ident("try_traverse");
+                    arms.map.push(quote! {
+                        Self::#ident(ref variant) => #c_ident::#ident(variant.#map_sort(f))
+                    });
+                    arms.map_owned.push(quote! {
+                        Self::#ident(variant) => #c_ident::#ident(variant.#map_owned_sort(f))
+                    });
+                    arms.map_mut.push(quote! {
+                        Self::#ident(variant) => variant.#map_mut_sort(f)
+                    });
+                    arms.try_traverse.push(quote! {
+                        Self::#ident(variant) => #c_ident::#ident(variant.#try_traverse_sort(f)?)
+                    });
+
+                    let pattern = destructure_fields(&fields, false);
+                    let map_mut_stmts = field_places(&fields, Place::Binding)
+                        .into_iter()
+                        .map(|(place, field)| map_mut_field(&sort.param, &place, field));
+                    let methods = sort_methods(sort, &ident, &generics, fields.iter(), &derives, SortBodies {
+                        map: {
+                            let mapped_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                                map_field(&sort.param, place, field)
+                            });
+                            quote! { #ident #mapped_fields }
+                        },
+                        map_owned: {
+                            let mapped_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                                map_owned_field(&sort.param, place, field)
+                            });
+                            quote! { #ident #mapped_fields }
+                        },
+                        map_mut: quote! {
+                            let #ident #pattern = self;
+                            #(#map_mut_stmts)*
+                        },
+                        try_traverse: {
+                            let traversed_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                                traverse_field(&sort.param, place, field)
+                            });
+                            quote! { #ident #traversed_fields }
+                        }
+                    });
+                    out.extend(quote! {
+                        impl#generics #ident#generics {
+                            #methods
+                        }
+                    });
+                } else {
+                    // the struct has no such children, so that it is cloned
+                    // field by field when borrowed
+                    let ref_pattern = destructure_fields(&fields, true);
+                    let cloned_fields = map_fields(&fields, Place::RefBinding, |place, field| {
+                        map_field(&sort.param, place, field)
+                    });
+                    arms.map.push(quote! {
+                        Self::#ident(#ident #ref_pattern) => #c_ident::#ident(#ident #cloned_fields)
+                    });
+                    arms.map_owned.push(quote! {
+                        Self::#ident(variant) => #c_ident::#ident(variant)
+                    });
+                    arms.map_mut.push(quote! {
+                        Self::#ident(_) => {}
+                    });
+                    arms.try_traverse.push(quote! {
+                        Self::#ident(variant) => #c_ident::#ident(variant)
+                    });
+                }
+            }
+            map_mut_arms.push(quote! {
+                Self::#ident(variant) => entish::MapMut::map_mut(variant, f)
+            });
+            zip_arms.push(quote! {
+                (&Self::#ident(ref variant), &#c_ident::#ident(ref other)) => {
+                    #c_ident::#ident(entish::Zip::zip_with(variant, other, f)?)
+                }
+            });
+            traverse_arms.push(quote! {
+                Self::#ident(variant) => #c_ident::#ident(
+                    entish::Traverse::<#child_ident, MapOutput>::try_traverse(variant, f)?
+                )
+            });
+            into_result_arms.push(quote! {
+                Self::#ident(variant) => #c_ident::#ident(variant.into_result()?)
+            });
+            into_option_arms.push(quote! {
+                Self::#ident(variant) => #c_ident::#ident(variant.into_option()?)
+            });
+            into_validated_arms.push(quote! {
+                Self::#ident(variant) => #c_ident::#ident(
+                    entish::IntoValidated::into_validated(variant)?
+                )
+            });
+            let has_child = generics.type_params().any(|TypeParam { ident, .. }| *ident == child_ident);
+            if !sorts.is_empty() {
+                let borrowed_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                    as_refs_expr(&child_idents, &field.ty, quote! { &#place })
+                });
+                let method = as_refs_method(
+                    &ident,
+                    &generics,
+                    fields.iter(),
+                    &child_idents,
+                    quote! { #ident #borrowed_fields }
+                );
+                out.extend(quote! {
+                    impl#generics #ident#generics {
+                        #method
+                    }
+                });
+                as_refs_arms.push(quote! {
+                    Self::#ident(ref variant) => #c_ident::#ident(variant.as_refs())
+                });
+            }
+            if has_child {
+                children_named_arms.push(quote! {
+                    Self::#ident(ref variant) => variant.children_named()
+                });
+            } else {
+                children_named_arms.push(quote! {
+                    Self::#ident(_) => Vec::new()
+                });
+            }
+
+            let ident_doc = format!(
+                "A node of type `{ident}` in a [{trait_}](trait.{trait_}.html)",
+                ident = ident,
+                trait_ = trait_ident
+            );
+            out.extend(quote! {
+                #[doc = #ident_doc]
+                #attributes
+                pub struct #ident#generics #fields_stream
+            });
+
+            if has_child {
+                let named_children = named_children_methods(
+                    &child_ident,
+                    children_named_body(children_named_stmts(&child_ident, &fields, Place::SelfField))
+                );
+                out.extend(quote! {
+                    impl#generics #ident#generics {
+                        #named_children
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::From) {
+                out.extend(quote! {
+                    impl#c_generics From<#ident#generics> for #c_ident#c_generics {
+                        fn from(variant: #ident#generics) -> Self {
+                            Self::#ident(variant)
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::TryInto) {
+                out.extend(quote! {
+                    impl#c_generics std::convert::TryInto<#ident#generics>
+                        for #c_ident#c_generics
+                    {
+                        type Error = ();
+                        fn try_into(self) ->
+                            std::result::Result<#ident#generics, Self::Error>
+                        {
+                            match self {
+                                Self::#ident(variant) => Ok(variant),
+                                _ => Err(())
+                            }
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::Map) ||
+                derives.contains(&SupportedDerives::MapOwned) ||
+                derives.contains(&SupportedDerives::MapMut) ||
+                derives.contains(&SupportedDerives::Traverse) ||
+                derives.contains(&SupportedDerives::Zip)
+            {
+                let map_output_ident = format_ident!("{}", MAP_OUTPUT);
+
+                let mapped_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                    map_field(&child_ident, place, field)
+                });
+
+                let mapped_fields_owned = map_fields(&fields, Place::SelfField, |place, field| {
+                    map_owned_field(&child_ident, place, field)
+                });
+
+                let traversed_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                    traverse_field(&child_ident, place, field)
+                });
+
+                let mut rg = ReplaceIdent::replace_with(
+                    format_ident!("{}", CHILD),
+                    map_output_ident.clone()
+                );
+                let mapped_generics = rg.fold_generics(generics.clone());
+
+                let mut map_generic_params = generics.params.clone();
+                add_bound_to_all(
+                    all_but_ident(map_generic_params.iter_mut(), format_ident!("{}", CHILD)),
+                    syn::parse2(quote! { 'a }).unwrap()
+                );
+
+                let mut map_owned_generic_params = generics.params.clone();
+
+                let zip_where_clause = where_clause(zip_predicates(&generics, fields.iter(), &child_ident));
+                let where_clause = where_clause(clone_predicates(&generics, fields.iter(), &[&child_ident]));
+
+                if ! has_child {
+                    map_generic_params.push(syn::parse2(quote! { #child_ident }).unwrap());
+                    map_owned_generic_params.push(syn::parse2(quote! { #child_ident }).unwrap());
+                }
+
+                if derives.contains(&SupportedDerives::Map) {
+                    out.extend(quote! {
+                        impl<'a, #map_output_ident: 'a, #map_generic_params>
+                            entish::Map<'a, &'a #child_ident, #map_output_ident>
+                            for #ident#generics
+                            #where_clause
+                        {
+                            type OuterO = #ident#mapped_generics;
+                            fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+                            where
+                                F: FnMut(&'a #child_ident) -> #map_output_ident
+                            {
+                                #ident #mapped_fields
+                            }
+                        }
+                    });
+                }
+
+                if derives.contains(&SupportedDerives::Zip) {
+                    let other_child_ident = format_ident!("{}", OTHER_CHILD);
+                    let mut ro = ReplaceIdent::replace_with(
+                        format_ident!("{}", CHILD),
+                        other_child_ident.clone()
+                    );
+                    let other_generics = ro.fold_generics(generics.clone());
+
+                    let ref_pattern = destructure_fields(&fields, true);
+                    let other_pattern = destructure_other_fields(&fields);
+                    let zipped_fields = zip_fields(&fields, Place::RefBinding, Place::OtherRefBinding, |place, other_place, field| {
+                        zip_field(&child_ident, place, other_place, field)
+                    });
+
+                    out.extend(quote! {
+                        impl<'a, #map_output_ident: 'a, #other_child_ident: 'a, #map_generic_params>
+                            entish::Zip<'a, &'a #child_ident, &'a #other_child_ident, #map_output_ident>
+                            for #ident#generics
+                            #zip_where_clause
+                        {
+                            type Other = #ident#other_generics;
+                            type OuterO = #ident#mapped_generics;
+                            fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+                            where
+                                F: FnMut(&'a #child_ident, &'a #other_child_ident) -> #map_output_ident
+                            {
+                                let #ident #ref_pattern = *self;
+                                let #ident #other_pattern = *other;
+                                Some(#ident #zipped_fields)
+                            }
+                        }
+                    });
+                }
+
+                if derives.contains(&SupportedDerives::MapOwned) {
+                    out.extend(quote! {
+                        impl<#map_output_ident, #map_owned_generic_params>
+                            entish::MapOwned<#child_ident, #map_output_ident>
+                            for #ident#generics
+                        {
+                            type OuterO = #ident#mapped_generics;
+                            fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+                            where
+                                F: FnMut(#child_ident) -> #map_output_ident
+                            {
+                                #ident #mapped_fields_owned
+                            }
+                        }
+                    });
+                }
+
+                if derives.contains(&SupportedDerives::MapMut) {
+                    let pattern = destructure_fields(&fields, false);
+                    let map_mut_stmts = field_places(&fields, Place::Binding)
+                        .into_iter()
+                        .map(|(place, field)| map_mut_field(&child_ident, &place, field));
+                    out.extend(quote! {
+                        impl<#map_owned_generic_params>
+                            entish::MapMut<#child_ident>
+                            for #ident#generics
+                        {
+                            fn map_mut<'a, F>(&'a mut self, f: &mut F)
+                            where
+                                F: FnMut(&'a mut #child_ident),
+                                #child_ident: 'a
+                            {
+                                let #ident #pattern = self;
+                                #(#map_mut_stmts)*
+                            }
+                        }
+                    });
+                }
+
+                if derives.contains(&SupportedDerives::Traverse) {
+                    out.extend(quote! {
+                        impl<#map_output_ident, #map_owned_generic_params>
+                            entish::Traverse<#child_ident, #map_output_ident>
+                            for #ident#generics
+                        {
+                            type OuterO = #ident#mapped_generics;
+                            fn try_traverse<__Error, F>(self, f: &mut F)
+                                -> std::result::Result<Self::OuterO, __Error>
+                            where
+                                F: FnMut(#child_ident) -> std::result::Result<#map_output_ident, __Error>
+                            {
+                                Ok(#ident #traversed_fields)
+                            }
+                        }
+                    });
+                }
+            }
+
+            if derives.contains(&SupportedDerives::IntoResult) {
+                let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+
+                let mut generics_with_e = generics.clone();
+                generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+
+                let generic_args: Punctuated<TokenStream, Comma> = generics
+                    .params
+                    .iter()
+                    .map(|param| {
+                        if *param == generic_param!(format_ident!("{}", CHILD)) {
+                            quote! { std::result::Result<#param, #err_tp> }
+                        } else {
+                            quote! { #param }
+                        }
+                    })
+                    .collect();
+
+                let mapped_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                    into_result_field(&child_ident, place, field)
+                });
+
+                out.extend(quote! {
+                    impl#generics_with_e
+                        entish::IntoResult<#ident#generics, #err_tp>
+                        for #ident<#generic_args>
+                    {
+                        fn into_result(self) -> std::result::Result<#ident#generics, #err_tp> {
+                            Ok(
+                                #ident #mapped_fields
+                            )
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::IntoOption) {
+                let generic_args: Punctuated<TokenStream, Comma> = generics
+                    .params
+                    .iter()
+                    .map(|param| {
+                        if *param == generic_param!(format_ident!("{}", CHILD)) {
+                            quote! { Option<#param> }
+                        } else {
+                            quote! { #param }
+                        }
+                    })
+                    .collect();
+
+                let mapped_fields = map_fields(&fields, Place::SelfField, |place, field| {
+                    into_option_field(&child_ident, place, field)
+                });
+
+                out.extend(quote! {
+                    impl#generics
+                        entish::IntoOption<#ident#generics>
+                        for #ident<#generic_args>
+                    {
+                        fn into_option(self) -> Option<#ident#generics> {
+                            Some(
+                                #ident #mapped_fields
+                            )
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::IntoValidated) {
+                let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+
+                let mut generics_with_e = generics.clone();
+                generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+
+                let generic_args: Punctuated<TokenStream, Comma> = generics
+                    .params
+                    .iter()
+                    .map(|param| {
+                        if *param == generic_param!(format_ident!("{}", CHILD)) {
+                            quote! { std::result::Result<#param, Vec<#err_tp>> }
+                        } else {
+                            quote! { #param }
+                        }
+                    })
+                    .collect();
+
+                let pattern = destructure_fields(&fields, false);
+                let validated = validated_node(&child_ident, &quote! { #ident }, &fields);
+
+                out.extend(quote! {
+                    impl#generics_with_e
+                        entish::IntoValidated<#ident#generics, #err_tp>
+                        for #ident<#generic_args>
+                    {
+                        fn into_validated(self) -> std::result::Result<#ident#generics, Vec<#err_tp>> {
+                            let #ident #pattern = self;
+                            Ok(#validated)
+                        }
+                    }
+                });
+            }
+        }
+
+        let named_children = named_children_methods(
+            &child_ident,
+            quote! {
+                match *self {
+                    #(#children_named_arms,)*
+                }
+            }
+        );
+        let as_refs = if sorts.is_empty() {
+            quote! {}
+        } else {
+            as_refs_method(&c_ident, &c_generics, all_fields.iter(), &child_idents, quote! {
+                match *self {
+                    #(#as_refs_arms,)*
+                }
+            })
+        };
+        let [name_arms, fields_arms, child_fields_arms, arity_arms] = kind_metadata_arms;
+        let kind_count = kinds.len();
+        let kind_doc = format!(
+            "The variants of [{c_ident}](enum.{c_ident}.html), without their fields.",
+            c_ident = c_ident
+        );
+        out.extend(quote! {
+            impl#c_generics #c_ident#c_generics {
+                #named_children
+
+                #as_refs
+
+                /// My variant, without its fields.
+                pub fn kind(&self) -> #kind_ident {
+                    match *self {
+                        #(#kind_arms,)*
+                    }
+                }
+            }
+
+            #[doc = #kind_doc]
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            pub enum #kind_ident {
+                #(#kinds,)*
+            }
+
+            impl #kind_ident {
+                /// All the kinds, in the order the variants are declared.
+                pub const ALL: [Self; #kind_count] = [#(Self::#kinds,)*];
+
+                /// The name of the variant.
+                pub fn name(self) -> &'static str {
+                    match self {
+                        #(#name_arms,)*
+                    }
+                }
+
+                /// The names of all the fields of the variant. Unnamed fields
+                /// are named by their index.
+                pub fn fields(self) -> &'static [&'static str] {
+                    match self {
+                        #(#fields_arms,)*
+                    }
+                }
+
+                /// The names of the fields of the variant that hold children.
+                pub fn child_fields(self) -> &'static [&'static str] {
+                    match self {
+                        #(#child_fields_arms,)*
+                    }
+                }
+
+                /// The number of children of the variant, if it does not
+                /// depend on the node, i.e. if none of them is held in a
+                /// container other than a tuple, an array or a `Box`.
+                pub fn arity(self) -> Option<usize> {
+                    match self {
+                        #(#arity_arms,)*
+                    }
+                }
+            }
+        });
+
+        if derives.contains(&SupportedDerives::Map) ||
+            derives.contains(&SupportedDerives::MapOwned) ||
+            derives.contains(&SupportedDerives::MapMut) ||
+            derives.contains(&SupportedDerives::Traverse) ||
+            derives.contains(&SupportedDerives::Zip)
+        {
+            let map_output_ident = format_ident!("{}", MAP_OUTPUT);
+
+            let mut rg = ReplaceIdent::replace_with(
+                format_ident!("{}", CHILD),
+                map_output_ident.clone()
+            );
+
+            let mapped_c_generics = rg.fold_generics(c_generics.clone());
+
+            let mut map_generic_params = c_generics.params.clone();
+            add_bound_to_all(
+                all_but_ident(map_generic_params.iter_mut(), format_ident!("{}", CHILD)),
+                syn::parse2(quote! { 'a }).unwrap()
+            );
+
+            let map_owned_generic_params = c_generics.params.clone();
+
+            let c_where_clause = where_clause(clone_predicates(&c_generics, all_fields.iter(), &[&child_ident]));
+
+            if derives.contains(&SupportedDerives::Map) {
+                out.extend(quote! {
+                    impl<'a, #map_output_ident: 'a, #map_generic_params>
+                        entish::Map<'a, &'a #child_ident, #map_output_ident>
+                        for #c_ident#c_generics
+                        #c_where_clause
+                    {
+                        type OuterO = #c_ident#mapped_c_generics;
+                        fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+                        where
+                            F: FnMut(&'a #child_ident) -> #map_output_ident
+                        {
+                            match *self {
+                                #(#map_arms,)*
+                            }
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::MapOwned) {
+                out.extend(quote! {
+                    impl<#map_output_ident, #map_owned_generic_params>
+                        entish::MapOwned<#child_ident, #map_output_ident>
+                        for #c_ident#c_generics
+                    {
+                        type OuterO = #c_ident#mapped_c_generics;
+                        fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+                        where
+                            F: FnMut(#child_ident) -> #map_output_ident
+                        {
+                            match self {
+                                #(#map_owned_arms,)*
+                            }
+                        }
+                    }
+                });
+            }
+
+            if !sorts.is_empty() {
+                let methods = sorts.iter().zip(sort_arms).map(|(sort, arms)| {
+                    let SortArms { map, map_owned, map_mut, try_traverse } = arms;
+                    sort_methods(sort, &c_ident, &c_generics, all_fields.iter(), &derives, SortBodies {
+                        map: quote! { match *self { #(#map,)* } },
+                        map_owned: quote! { match self { #(#map_owned,)* } },
+                        map_mut: quote! { match self { #(#map_mut,)* } },
+                        try_traverse: quote! { match self { #(#try_traverse,)* } }
+                    })
+                });
+                out.extend(quote! {
+                    impl#c_generics #c_ident#c_generics {
+                        #(#methods)*
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::Zip) {
+                let other_child_ident = format_ident!("{}", OTHER_CHILD);
+                let mut ro = ReplaceIdent::replace_with(
+                    format_ident!("{}", CHILD),
+                    other_child_ident.clone()
+                );
+                let other_c_generics = ro.fold_generics(c_generics.clone());
+                let zip_where_clause = where_clause(zip_predicates(&c_generics, all_fields.iter(), &child_ident));
+
+                out.extend(quote! {
+                    impl<'a, #map_output_ident: 'a, #other_child_ident: 'a, #map_generic_params>
+                        entish::Zip<'a, &'a #child_ident, &'a #other_child_ident, #map_output_ident>
+                        for #c_ident#c_generics
+                        #zip_where_clause
+                    {
+                        type Other = #c_ident#other_c_generics;
+                        type OuterO = #c_ident#mapped_c_generics;
+                        fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+                        where
+                            F: FnMut(&'a #child_ident, &'a #other_child_ident) -> #map_output_ident
+                        {
+                            let zipped = match (self, other) {
+                                #(#zip_arms,)*
+                                #[allow(unreachable_patterns)]
+                                _ => return None
+                            };
+                            Some(zipped)
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::MapMut) {
+                out.extend(quote! {
+                    impl<#map_owned_generic_params>
+                        entish::MapMut<#child_ident>
+                        for #c_ident#c_generics
+                    {
+                        fn map_mut<'a, F>(&'a mut self, f: &mut F)
+                        where
+                            F: FnMut(&'a mut #child_ident),
+                            #child_ident: 'a
+                        {
+                            match self {
+                                #(#map_mut_arms,)*
+                            }
+                        }
+                    }
+                });
+            }
+
+            if derives.contains(&SupportedDerives::Traverse) {
+                out.extend(quote! {
+                    impl<#map_output_ident, #map_owned_generic_params>
+                        entish::Traverse<#child_ident, #map_output_ident>
+                        for #c_ident#c_generics
+                    {
+                        type OuterO = #c_ident#mapped_c_generics;
+                        fn try_traverse<__Error, F>(self, f: &mut F)
+                            -> std::result::Result<Self::OuterO, __Error>
+                        where
+                            F: FnMut(#child_ident) -> std::result::Result<#map_output_ident, __Error>
+                        {
+                            Ok(
+                                match self {
+                                    #(#traverse_arms,)*
+                                }
+                            )
+                        }
+                    }
+                });
+            }
+        }
+
+        if derives.contains(&SupportedDerives::IntoOption) {
+            let generic_args: Punctuated<TokenStream, Comma> = c_generics
+                .params
+                .iter()
+                .map(|param| {
+                    if *param == generic_param!(format_ident!("{}", CHILD)) {
+                        quote! { Option<#param> }
+                    } else {
+                        quote! { #param }
+                    }
+                })
+                .collect();
+
+            out.extend(quote! {
+                impl#c_generics
+                    entish::IntoOption<#c_ident#c_generics>
+                    for #c_ident<#generic_args>
+                {
+                    fn into_option(self) -> Option<#c_ident#c_generics> {
+                        Some(
+                            match self {
+                                #(#into_option_arms,)*
+                            }
+                        )
+                    }
+                }
+            });
+        }
+
+        if derives.contains(&SupportedDerives::IntoResult) {
+            let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+
+            let mut c_generics_with_e = c_generics.clone();
+            c_generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+
+            let generic_args: Punctuated<TokenStream, Comma> = c_generics
+                .params
+                .iter()
+                .map(|param| {
+                    if *param == generic_param!(format_ident!("{}", CHILD)) {
+                        quote! { std::result::Result<#param, #err_tp> }
+                    } else {
+                        quote! { #param }
+                    }
+                })
+                .collect();
+
+            out.extend(quote! {
+                impl#c_generics_with_e
+                    entish::IntoResult<#c_ident#c_generics, #err_tp>
+                    for #c_ident<#generic_args>
+                {
+                    fn into_result(self) -> std::result::Result<#c_ident#c_generics, #err_tp> {
+                        Ok(
+                            match self {
+                                #(#into_result_arms,)*
+                            }
+                        )
+                    }
+                }
+            });
+        }
+
+        if derives.contains(&SupportedDerives::IntoValidated) {
+            let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+
+            let mut c_generics_with_e = c_generics.clone();
+            c_generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+
+            let generic_args: Punctuated<TokenStream, Comma> = c_generics
+                .params
+                .iter()
+                .map(|param| {
+                    if *param == generic_param!(format_ident!("{}", CHILD)) {
+                        quote! { std::result::Result<#param, Vec<#err_tp>> }
+                    } else {
+                        quote! { #param }
+                    }
+                })
+                .collect();
+
+            out.extend(quote! {
+                impl#c_generics_with_e
+                    entish::IntoValidated<#c_ident#c_generics, #err_tp>
+                    for #c_ident<#generic_args>
+                {
+                    fn into_validated(self) -> std::result::Result<#c_ident#c_generics, Vec<#err_tp>> {
+                        Ok(
+                            match self {
+                                #(#into_validated_arms,)*
+                            }
+                        )
+                    }
+                }
+            });
+        }
+
+        let mut c_generics_no_child = c_generics.clone();
+        c_generics_no_child.params.pop();
+
+        // in a family, the `*Tree` trait has an associated type for each
+        // other type of children, e.g. `StmtChild`, instead of a parameter
+        let sort_params = &child_idents[1..];
+        let mut trait_generics = c_generics_no_child.clone();
+        trait_generics.params = trait_generics.params
+            .into_iter()
+            .filter(|gp| match gp {
+                GenericParam::Type(TypeParam { ident, .. }) => !sort_params.contains(&ident),
+                _ => true
+            })
+            .collect();
+        let trait_args = generic_args(&trait_generics);
+        let trait_ref = if trait_args.is_empty() && sort_params.is_empty() {
+            quote! { #trait_ident }
+        } else {
+            quote! { #trait_ident<#(#trait_args,)* #(#sort_params = #sort_params),*> }
+        };
+        let trait_types: Vec<TokenStream> = sort_params
+            .iter()
+            .map(|param| {
+                let doc = format!("The type of my children of type `{}`", param);
+                quote! {
+                    #[doc = #doc]
+                    type #param;
+                }
+            })
+            .collect();
+        // the arguments of nodes in the `*Tree` trait, ending with `child`
+        let trait_node_args = |child: TokenStream| -> Punctuated<TokenStream, Comma> {
+            let mut args: Punctuated<TokenStream, Comma> = c_generics_no_child
+                .params
+                .iter()
+                .map(|c| match c {
+                    GenericParam::Type(TypeParam { ident, .. }) if sort_params.contains(&ident) => {
+                        quote! { Self::#ident }
+                    },
+                    _ => quote! { #c }
+                })
+                .collect();
+            args.push(child);
+            args
+        };
+
+        let c_generics_with_o = trait_node_args(quote! { O });
+
+        // children of other types are borrowed along with the ones of type
+        // `Self`
+        let mut c_generics_with_ref_self: Punctuated<TokenStream, Comma> = c_generics_no_child
+            .params
+            .iter()
+            .map(|c| match c {
+                GenericParam::Type(TypeParam { ident, .. }) if sort_params.contains(&ident) => {
+                    quote! { &Self::#ident }
+                },
+                _ => quote! { #c }
+            })
+            .collect();
+        c_generics_with_ref_self.push(quote! { &Self });
+
+        let c_generics_with_self = trait_node_args(quote! { Self });
 
-            if derives.contains(&SupportedDerives::From) {
-                out.extend(quote! {
-                    impl#c_generics From<#ident#generics> for #c_ident#c_generics {
-                        fn from(variant: #ident#generics) -> Self {
-                            Self::#ident(variant)
-                        }
-                    }
-                });
+        let c_generics_with_unit = trait_node_args(quote! { () });
+
+        // splits a tree into its root node without children and its children
+        let unfold = quote! {
+            &mut |tree: Self| {
+                let mut children = Vec::new();
+                let shape = entish::MapOwned::map_owned(
+                    tree.into_inner(),
+                    &mut |c: Self| children.push(c)
+                );
+                (shape, children)
             }
+        };
 
-            if derives.contains(&SupportedDerives::TryInto) {
-                out.extend(quote! {
-                    impl#c_generics std::convert::TryInto<#ident#generics>
-                        for #c_ident#c_generics
-                    {
-                        type Error = ();
-                        fn try_into(self) ->
-                            std::result::Result<#ident#generics, Self::Error>
-                        {
-                            match self {
-                                Self::#ident(variant) => Ok(variant),
-                                _ => Err(())
-                            }
+        // rebuilds a node from its shape and the outputs for its children
+        let refill = quote! {
+            {
+                let mut outputs = outputs.into_iter();
+                entish::MapOwned::map_owned(shape, &mut |()| outputs.next().unwrap())
+            }
+        };
+
+        let try_fold_all = if derives.contains(&SupportedDerives::IntoValidated) {
+            quote! {
+                /// Like `try_fold` but keeps going when the operation fails,
+                /// to return the errors of all the nodes where it did, along
+                /// with their paths. Nodes with a failed child are skipped.
+                fn try_fold_all<F, O, E>(self, f: &mut F)
+                    -> std::result::Result<O, Vec<entish::Located<E>>>
+                where
+                    F: FnMut(#c_ident<#c_generics_with_o>) -> std::result::Result<O, E>
+                {
+                    // paths are built from leaves to root, so reversed
+                    let out = entish::fold::fold(
+                        self,
+                        #unfold,
+                        &mut |
+                            shape: #c_ident<#c_generics_with_unit>,
+                            outputs: Vec<std::result::Result<O, Vec<entish::Located<E>>>>
+                        | {
+                            let mut outputs = outputs
+                                .into_iter()
+                                .enumerate()
+                                .map(|(idx, output)| output.map_err(|mut errors| {
+                                    errors.iter_mut().for_each(|error| error.path.push(idx));
+                                    errors
+                                }));
+                            let node = entish::MapOwned::map_owned(shape, &mut |()| outputs.next().unwrap());
+                            f(entish::IntoValidated::into_validated(node)?).map_err(|error| {
+                                vec![entish::Located { path: Vec::new(), error }]
+                            })
                         }
-                    }
-                });
+                    );
+                    out.map_err(|mut errors| {
+                        errors.iter_mut().for_each(|error| error.path.reverse());
+                        errors
+                    })
+                }
             }
+        } else {
+            quote! {}
+        };
 
-            if derives.contains(&SupportedDerives::Map) ||
-                derives.contains(&SupportedDerives::MapOwned)
+        // splits a borrowed tree into the shape of its root node and its
+        // children
+        let unfold_ref = quote! {
+            &mut |tree| {
+                let mut children = Vec::new();
+                let shape = entish::MapOwned::map_owned(Self::as_ref(tree), &mut |c| children.push(c));
+                (shape, children)
+            }
+        };
+        let shape = quote! { #c_ident<#c_generics_with_unit> };
+        // the shapes split by `unfold_ref`, which borrow the children of
+        // other types
+        let ref_shape = if sorts.is_empty() {
+            shape.clone()
+        } else {
+            let args = c_generics_no_child.params.iter().map(|gp| match gp {
+                GenericParam::Type(TypeParam { ident, .. }) if sort_params.contains(&ident) => {
+                    quote! { &'__shape Self::#ident }
+                },
+                _ => quote! { #gp }
+            });
+            quote! { for<'__shape> #c_ident<#(#args,)* ()> }
+        };
+
+        let mut tree_cmp_methods = Vec::new();
+        if node_derives.contains("PartialEq") {
+            tree_cmp_methods.push(quote! {
+                /// Whether the tree is equal to `other`, i.e. all of their
+                /// nodes are equal when ignoring their children. This does
+                /// not recurse, so it works on trees of any depth.
+                fn tree_eq(&self, other: &Self) -> bool
+                where
+                    #ref_shape: PartialEq
+                {
+                    entish::cmp::eq(self, other, #unfold_ref)
+                }
+            });
+        }
+        if node_derives.contains("PartialOrd") {
+            tree_cmp_methods.push(quote! {
+                /// Compare the tree with `other` by comparing their nodes,
+                /// ignoring their children, in pre-order. This does not
+                /// recurse, so it works on trees of any depth.
+                fn tree_partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>
+                where
+                    #ref_shape: PartialOrd
+                {
+                    entish::cmp::partial_cmp(self, other, #unfold_ref)
+                }
+            });
+        }
+        if node_derives.contains("Ord") {
+            tree_cmp_methods.push(quote! {
+                /// Like `tree_partial_cmp` but for a total order.
+                fn tree_cmp(&self, other: &Self) -> std::cmp::Ordering
+                where
+                    #ref_shape: Ord
+                {
+                    entish::cmp::cmp(self, other, #unfold_ref)
+                }
+            });
+        }
+        if node_derives.contains("Hash") {
+            tree_cmp_methods.push(quote! {
+                /// Hash the tree by hashing its nodes, ignoring their
+                /// children, in pre-order. Equal trees as per `tree_eq` have
+                /// equal hashes. This does not recurse, so it works on trees
+                /// of any depth.
+                fn tree_hash<H>(&self, state: &mut H)
+                where
+                    H: std::hash::Hasher,
+                    #ref_shape: std::hash::Hash
+                {
+                    entish::cmp::hash(self, #unfold_ref, state)
+                }
+            });
+        }
+
+        let trait_doc = format!(
+            "A trait for types that are like a tree whose nodes are described by [{c_ident}](enum.{c_ident}.html).",
+            c_ident = c_ident
+        );
+
+        out.extend(quote! {
+            #[doc = #trait_doc]
+            pub trait #trait_ident#trait_generics: Sized
             {
-                let map_output_ident = format_ident!("{}", MAP_OUTPUT);                
-
-                let mapped_fields = map_fields(&fields, |ident, field| {
-                    let ty = &field.ty;
-                    if is_ident(ty, &child_ident) {
-                        // assumes nesting gp
-                        quote! { f(&self.#ident) }
-                    } else if contains_ident(&field.ty, &child_ident) {
-                        // assumes container type
-                        quote! { <#ty as Map<&'a Child, MapOutput>>::map(&self.#ident, f) }
-                    } else {
-                        // assumes has to move
-                        quote! { self.#ident.clone() }
-                    }
-                });
+                #(#trait_types)*
 
-                let mapped_fields_owned = map_fields(&fields, |ident, field| {
-                    let ty = &field.ty;
-                    if is_ident(ty, &child_ident) {
-                        // assumes nesting gp
-                        quote! { f(self.#ident) }
-                    } else if contains_ident(&field.ty, &child_ident) {
-                        // assumes container type
-                        quote! { <#ty as MapOwned<Child, MapOutput>>::map_owned(self.#ident, f) }
-                    } else {
-                        // assumes has to move
-                        quote! { self.#ident.clone() }
-                    }
-                });
+                /// Unravel a node whose children are references to my
+                /// children
+                fn as_ref(&self) -> #c_ident<#c_generics_with_ref_self>;
 
-                let mut rg = ReplaceIdent::replace_with(
-                    format_ident!("{}", CHILD),
-                    map_output_ident.clone()
-                );
-                let mapped_generics = rg.fold_generics(generics.clone());
+                fn into_inner(self) -> #c_ident<#c_generics_with_self>;
 
-                let mut map_generic_params = generics.params.clone();
-                add_bound_to_all(
-                    all_but_ident(map_generic_params.iter_mut(), format_ident!("{}", CHILD)),
-                    syn::parse2(quote! { 'a }).unwrap()
-                );
+                /// Reduce the tree to a single value using by folding a
+                /// closure, reducing from leaves to root. This does not
+                /// recurse, so it works on trees of any depth.
+                fn fold<F, O>(self, f: &mut F) -> O
+                where
+                    F: FnMut(#c_ident<#c_generics_with_o>) -> O
+                {
+                    entish::fold::fold(
+                        self,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<O>| {
+                            f(#refill)
+                        }
+                    )
+                }
 
-               let mut map_owned_generic_params = generics.params.clone(); 
+                /// Like `fold` but when the operation can fail. Stops at the
+                /// first error.
+                fn try_fold<F, O, E>(self, f: &mut F) -> std::result::Result<O, E>
+                where
+                    F: FnMut(#c_ident<#c_generics_with_o>) -> std::result::Result<O, E>
+                {
+                    entish::fold::try_fold(
+                        self,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<O>| {
+                            f(#refill)
+                        }
+                    )
+                }
 
-                let where_clause = where_clause_for_generics(generics.params.iter());
+                #try_fold_all
 
-                let has_child = map_generic_params
-                    .iter()
-                    .any(|p| {
-                        match p {
-                            GenericParam::Type(TypeParam { ident, .. }) => {
-                                ident == &child_ident
-                            },
-                            _ => false
+                #(#tree_cmp_methods)*
+
+                /// Rebuild the tree by applying `f` to every node, from
+                /// leaves to root. The children of the node `f` is given have
+                /// already been rewritten.
+                fn transform_up<F>(self, f: &mut F) -> Self
+                where
+                    F: FnMut(Self) -> Self,
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    entish::fold::fold(
+                        self,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<Self>| {
+                            f(Self::from(#refill))
                         }
-                    });
+                    )
+                }
 
-                if ! has_child {
-                    map_generic_params.push(syn::parse2(quote! { #child_ident }).unwrap());
-                    map_owned_generic_params.push(syn::parse2(quote! { #child_ident }).unwrap());
+                /// Rebuild the tree by applying `f` to every node, from root
+                /// to leaves. The children of the node returned by `f` are
+                /// rewritten next.
+                fn transform_down<F>(self, f: &mut F) -> Self
+                where
+                    F: FnMut(Self) -> Self,
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    entish::fold::fold_with(
+                        self,
+                        f,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<Self>| {
+                            Self::from(#refill)
+                        }
+                    )
                 }
 
-                if derives.contains(&SupportedDerives::Map) {
-                    out.extend(quote! {
-                        impl<'a, #map_output_ident: 'a, #map_generic_params>
-                            entish::Map<'a, &'a #child_ident, #map_output_ident>
-                            for #ident#generics
-                            #where_clause
-                        {
-                            type OuterO = #ident#mapped_generics;
-                            fn map<F>(&'a self, f: &mut F) -> Self::OuterO
-                            where
-                                F: FnMut(&'a #child_ident) -> #map_output_ident
-                            {
-                                #ident #mapped_fields
-                            }
+                /// Like `transform_up` but when the rewrite can fail. Stops
+                /// at the first error.
+                fn try_transform_up<F, E>(self, f: &mut F) -> std::result::Result<Self, E>
+                where
+                    F: FnMut(Self) -> std::result::Result<Self, E>,
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    entish::fold::try_fold(
+                        self,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<Self>| {
+                            f(Self::from(#refill))
                         }
-                    });
+                    )
                 }
 
-                if derives.contains(&SupportedDerives::MapOwned) {
-                    out.extend(quote! {
-                        impl<#map_output_ident, #map_owned_generic_params>
-                            entish::MapOwned<#child_ident, #map_output_ident>
-                            for #ident#generics
-                            #where_clause
-                        {
-                            type OuterO = #ident#mapped_generics;
-                            fn map_owned<F>(self, f: &mut F) -> Self::OuterO
-                            where
-                                F: FnMut(#child_ident) -> #map_output_ident
-                            {
-                                #ident #mapped_fields_owned
-                            }
+                /// Like `transform_down` but when the rewrite can fail.
+                /// Stops at the first error.
+                fn try_transform_down<F, E>(self, f: &mut F) -> std::result::Result<Self, E>
+                where
+                    F: FnMut(Self) -> std::result::Result<Self, E>,
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    entish::fold::try_fold_with(
+                        self,
+                        f,
+                        #unfold,
+                        &mut |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<Self>| {
+                            Ok(Self::from(#refill))
                         }
-                    });                    
+                    )
                 }
-            }
 
-            if derives.contains(&SupportedDerives::IntoResult) {
-                let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+                /// Apply `f` in place to every node of the tree, from root to
+                /// leaves. This does not recurse, so it works on trees of any
+                /// depth.
+                fn for_each_mut<F>(&mut self, f: &mut F)
+                where
+                    F: FnMut(&mut Self),
+                    Self: entish::MapMut<Self>
+                {
+                    entish::fold::for_each_mut(self, f)
+                }
 
-                let mut generics_with_e = generics.clone();
-                generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+                /// Rewrite the tree in place by applying `f` to every node,
+                /// from leaves to root. The children of the node `f` is given
+                /// have already been rewritten.
+                fn transform_up_mut<F>(&mut self, f: &mut F)
+                where
+                    F: FnMut(&mut Self),
+                    Self: entish::MapMut<Self>
+                {
+                    entish::fold::for_each_mut_up(self, f)
+                }
 
-                let generic_args: Punctuated<TokenStream, Comma> = generics
-                    .params
-                    .iter()
-                    .cloned()
-                    .map(|param| {
-                        if param == generic_param!(format_ident!("{}", CHILD)) {
-                            quote! { std::result::Result<#param, #err_tp> }
-                        } else {
-                            quote! { #param }
+                /// Focus on the root of the tree to move around it and edit
+                /// it node by node, see `entish::zipper`.
+                fn zipper(self) -> entish::zipper::Zipper<Self, #c_ident<#c_generics_with_unit>>
+                where
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    entish::zipper::Zipper::new(
+                        self,
+                        |tree: Self| {
+                            let mut children = Vec::new();
+                            let shape = entish::MapOwned::map_owned(
+                                tree.into_inner(),
+                                &mut |c: Self| children.push(c)
+                            );
+                            (shape, children)
+                        },
+                        |shape: #c_ident<#c_generics_with_unit>, outputs: Vec<Self>| {
+                            Self::from(#refill)
+                        }
+                    )
+                }
+
+                /// Apply the rules of `rules` to every node of the tree
+                /// according to `strategy`, see `entish::rewrite`.
+                fn rewrite(
+                    self,
+                    rules: &entish::rewrite::RuleSet<Self>,
+                    strategy: entish::rewrite::Strategy
+                ) -> (Self, entish::rewrite::Report)
+                where
+                    Self: From<#c_ident<#c_generics_with_self>>
+                {
+                    rules.run(self, strategy, &mut |tree: Self, order, f: &mut dyn FnMut(Self) -> Self| {
+                        match order {
+                            entish::rewrite::Order::BottomUp => tree.transform_up(&mut |node| f(node)),
+                            entish::rewrite::Order::TopDown => tree.transform_down(&mut |node| f(node))
                         }
                     })
-                    .collect();
+                }
 
-                let mapped_fields = map_fields(&fields, |ident, field| {
-                    if is_ident(&field.ty, &child_ident) {
-                        // assumes nesting gp
-                        quote! { self.#ident? }
-                    } else if contains_ident(&field.ty, &child_ident) {
-                        // assumes container type
-                        quote! { self.#ident.into_result()? }
-                    } else {
-                        // assumes has to move
-                        quote! { self.#ident }
-                    }
-                });
+                /// The variant of the root, without its fields.
+                fn kind(&self) -> #kind_ident {
+                    self.as_ref().kind()
+                }
 
-                out.extend(quote! {
-                    impl#generics_with_e
-                        entish::IntoResult<#ident#generics, #err_tp>
-                        for #ident<#generic_args>
-                    {
-                        fn into_result(self) -> std::result::Result<#ident#generics, #err_tp> {
-                            Ok(
-                                #ident #mapped_fields
-                            )
-                        }
-                    }
-                });
-            }
+                /// Get an iterator over references to children of this node
+                fn iter_children(&self) -> std::vec::IntoIter<&Self>
+                {
+                    let mut children = Vec::new();
+                    entish::MapOwned::map_owned(self.as_ref(), &mut |c| children.push(c));
+                    children.into_iter()
+                }
 
-            if derives.contains(&SupportedDerives::IntoOption) {
-                let generic_args: Punctuated<TokenStream, Comma> = generics
-                    .params
-                    .iter()
-                    .cloned()
-                    .map(|param| {
-                        if param == generic_param!(format_ident!("{}", CHILD)) {
-                            quote! { Option<#param> }
-                        } else {
-                            quote! { #param }
-                        }
+                /// Get an iterator over references to all the nodes of the
+                /// tree, every node before its children. This does not
+                /// recurse, so it works on trees of any depth.
+                fn iter_preorder(&self) -> entish::iter::Preorder<'_, Self>
+                {
+                    entish::iter::Preorder::new(self, Self::iter_children)
+                }
+
+                /// Like `iter_preorder` but also yields the depth of every
+                /// node, the root being at depth `0`.
+                fn iter_preorder_with_depth(&self) -> entish::iter::PreorderWithDepth<'_, Self>
+                {
+                    entish::iter::PreorderWithDepth::new(self, Self::iter_children)
+                }
+
+                /// Get an iterator over references to all the nodes of the
+                /// tree, every node after its children. This does not
+                /// recurse, so it works on trees of any depth.
+                fn iter_postorder(&self) -> entish::iter::Postorder<'_, Self>
+                {
+                    entish::iter::Postorder::new(self, Self::iter_children)
+                }
+
+                /// Like `iter_postorder` but also yields the depth of every
+                /// node, the root being at depth `0`.
+                fn iter_postorder_with_depth(&self) -> entish::iter::PostorderWithDepth<'_, Self>
+                {
+                    entish::iter::PostorderWithDepth::new(self, Self::iter_children)
+                }
+
+                /// Get an iterator over references to all the nodes of the
+                /// tree, level by level from the root.
+                fn iter_bfs(&self) -> entish::iter::Bfs<'_, Self>
+                {
+                    entish::iter::Bfs::new(self, Self::iter_children)
+                }
+
+                /// Like `iter_bfs` but also yields the depth of every node,
+                /// the root being at depth `0`.
+                fn iter_bfs_with_depth(&self) -> entish::iter::BfsWithDepth<'_, Self>
+                {
+                    entish::iter::BfsWithDepth::new(self, Self::iter_children)
+                }
+
+                /// Get an iterator over references to all the nodes of the
+                /// tree along with their paths, every node before its
+                /// children. Paths go down named fields by name, e.g.
+                /// `left` or `children[2]`, and unnamed ones by index.
+                fn iter_paths(&self) -> entish::path::Paths<'_, Self>
+                {
+                    entish::path::Paths::new(self, |tree| {
+                        tree.as_ref()
+                            .children_named()
+                            .into_iter()
+                            .map(|(name, child)| (name, *child))
+                            .collect()
                     })
-                    .collect();
+                }
 
-                let mapped_fields = map_fields(&fields, |ident, field| {
-                    if is_ident(&field.ty, &child_ident) {
-                        // assumes nesting gp
-                        quote! { self.#ident? }
-                    } else if contains_ident(&field.ty, &child_ident) {
-                        // assumes container type
-                        quote! { self.#ident.into_option()? }
-                    } else {
-                        // assumes has to move
-                        quote! { self.#ident }
+                /// Get the node at `path` from this one, if there is one.
+                fn get(&self, path: &entish::path::TreePath) -> Option<&Self>
+                {
+                    let mut tree = self;
+                    for step in path.steps() {
+                        let fields = tree.as_ref().child_fields();
+                        tree = step.select(&fields, tree.iter_children().collect())?;
                     }
-                });
+                    Some(tree)
+                }
 
-                out.extend(quote! {
-                    impl#generics
-                        entish::IntoOption<#ident#generics>
-                        for #ident<#generic_args>
-                    {
-                        fn into_option(self) -> Option<#ident#generics> {
-                            Some(
-                                #ident #mapped_fields
-                            )
-                        }
+                /// Like `get` but mutable.
+                fn get_mut(&mut self, path: &entish::path::TreePath) -> Option<&mut Self>
+                where
+                    Self: entish::MapMut<Self>
+                {
+                    let mut tree = self;
+                    for step in path.steps() {
+                        let fields = tree.as_ref().child_fields();
+                        let mut children = Vec::new();
+                        entish::MapMut::map_mut(tree, &mut |c| children.push(c));
+                        tree = step.select(&fields, children)?;
                     }
-                });
+                    Some(tree)
+                }
+
+                /// Replace the node at `path` from this one by `new`,
+                /// returning the node replaced, or `new` back if there is no
+                /// node at `path`.
+                fn replace_at(&mut self, path: &entish::path::TreePath, new: Self)
+                    -> std::result::Result<Self, Self>
+                where
+                    Self: entish::MapMut<Self>
+                {
+                    match self.get_mut(path) {
+                        Some(tree) => Ok(std::mem::replace(tree, new)),
+                        None => Err(new)
+                    }
+                }
             }
-        }
+        });
 
-        if derives.contains(&SupportedDerives::Map) ||
-            derives.contains(&SupportedDerives::MapOwned)
         {
-            let map_output_ident = format_ident!("{}", MAP_OUTPUT);
-
-            let map_variants: Punctuated<TokenStream, Comma> = variants.iter()
-                .map(|variant| {
-                    let ident = &variant.ident;
-                    quote! { Self::#ident(variant) => variant.map(f).into() }
-                })
+            let c_params: Vec<&GenericParam> = c_generics_no_child.params.iter().collect();
+            let c_args = generic_args(&c_generics_no_child);
+            let visit_ident = format_ident!("{}Visit", c_ident);
+            let fold_ident = format_ident!("{}Fold", c_ident);
+            let tree_param = format_ident!("{}", TREE_PARAM);
+            let visit_arms = visit_folds.iter().map(|vf| &vf.visit_arm);
+            let fold_arms = visit_folds.iter().map(|vf| &vf.fold_arm);
+            let mut seen = HashSet::new();
+            let visit_bounds: Vec<&TokenStream> = visit_folds
+                .iter()
+                .flat_map(|vf| vf.visit_bounds.iter())
+                .filter(|bound| seen.insert(bound.to_string()))
                 .collect();
-
-            let map_variants_owned: Punctuated<TokenStream, Comma> = variants.iter()
-                .map(|variant| {
-                    let ident = &variant.ident;
-                    quote! { Self::#ident(variant) => variant.map_owned(f).into() }
-                })
+            let visit_methods = visit_folds.iter().map(|vf| {
+                let (doc, signature, body) = &vf.visit_method;
+                quote! {
+                    #[doc = #doc]
+                    #signature
+                    where
+                        #(#visit_bounds,)*
+                    {
+                        #body
+                    }
+                }
+            });
+            // the fields of nodes are borrowed for `'tree`, including the
+            // children of other types
+            let type_params = c_generics_no_child.type_params().map(|tp| &tp.ident);
+            // the types children are held as, by default behind a `Box`
+            let held = held_param(None);
+            let sort_helds: Vec<Ident> = sort_params.iter().map(|param| held_param(Some(param))).collect();
+            let held_args = generic_args(&c_generics_no_child)
+                .into_iter()
+                .zip(c_generics_no_child.params.iter())
+                .map(|(arg, gp)| match gp {
+                    GenericParam::Type(TypeParam { ident, .. }) if sort_params.contains(&ident) => {
+                        let held = held_param(Some(ident));
+                        quote! { #held }
+                    },
+                    _ => arg
+                });
+            let fold_bounds: Vec<&TokenStream> = visit_folds
+                .iter()
+                .flat_map(|vf| vf.fold_bounds.iter())
+                .filter(|bound| seen.insert(bound.to_string()))
                 .collect();
+            let fold_methods = visit_folds.iter().map(|vf| {
+                let (doc, signature, body) = &vf.fold_method;
+                quote! {
+                    #[doc = #doc]
+                    #signature
+                    where
+                        #(#fold_bounds,)*
+                    {
+                        #body
+                    }
+                }
+            });
 
-            let mut rg = ReplaceIdent::replace_with(
-                format_ident!("{}", CHILD),
-                map_output_ident.clone()
+            let visit_doc = format!(
+                "A visitor of trees [{trait_}](trait.{trait_}.html) that deref to their root node, with a method per variant of [{c_ident}](enum.{c_ident}.html) to override, borrowing the fields of the node. Children are held as `Box`es unless given otherwise. This recurses into children, so trees must not be too deep.",
+                trait_ = trait_ident,
+                c_ident = c_ident
             );
-
-            let mapped_c_generics = rg.fold_generics(c_generics.clone());
-
-            let mut map_generic_params = c_generics.params.clone();
-            add_bound_to_all(
-                all_but_ident(map_generic_params.iter_mut(), format_ident!("{}", CHILD)),
-                syn::parse2(quote! { 'a }).unwrap()
+            let fold_doc = format!(
+                "Rebuilds trees [{trait_}](trait.{trait_}.html), with a method per variant of [{c_ident}](enum.{c_ident}.html) to override. This recurses into children, so trees must not be too deep.",
+                trait_ = trait_ident,
+                c_ident = c_ident
             );
 
-            let map_owned_generic_params = c_generics.params.clone();
+            out.extend(quote! {
+                #[doc = #visit_doc]
+                pub trait #visit_ident<
+                    'tree,
+                    #(#c_params,)*
+                    #tree_param,
+                    #(#sort_helds = std::boxed::Box<#sort_params>,)*
+                    #held = std::boxed::Box<#tree_param>
+                >
+                where
+                    #tree_param: #trait_ref + std::ops::Deref<Target = #c_ident<#(#held_args,)* #held>> + 'tree,
+                    #held: std::ops::Deref<Target = #tree_param> + 'tree,
+                    #(#type_params: 'tree,)*
+                    #(#sort_helds: 'tree,)*
+                {
+                    /// Visit `tree`. By default, calls the method for the
+                    /// variant of its root.
+                    fn visit(&mut self, tree: &'tree #tree_param)
+                    where
+                        #(#visit_bounds,)*
+                    {
+                        match &**tree {
+                            #(#visit_arms,)*
+                        }
+                    }
 
-            let c_where_clause = where_clause_for_generics(c_generics.params.iter());
+                    #(#visit_methods)*
+                }
 
-            if derives.contains(&SupportedDerives::Map) {
-                out.extend(quote! {
-                    impl<'a, #map_output_ident: 'a, #map_generic_params>
-                        entish::Map<'a, &'a #child_ident, #map_output_ident>
-                        for #c_ident#c_generics
-                        #c_where_clause
+                #[doc = #fold_doc]
+                pub trait #fold_ident<#(#c_params,)* #tree_param>
+                where
+                    #tree_param: #trait_ref + From<#c_ident<#(#c_args,)* #tree_param>>
+                {
+                    /// Fold `tree`. By default, calls the method for the
+                    /// variant of its root.
+                    fn fold(&mut self, tree: #tree_param) -> #tree_param
+                    where
+                        #(#fold_bounds,)*
                     {
-                        type OuterO = #c_ident#mapped_c_generics;
-                        fn map<F>(&'a self, f: &mut F) -> Self::OuterO
-                        where
-                            F: FnMut(&'a #child_ident) -> #map_output_ident
-                        {
-                            match self {
-                                #map_variants
-                            }
+                        match tree.into_inner() {
+                            #(#fold_arms,)*
                         }
                     }
-                });
-            }
 
-            if derives.contains(&SupportedDerives::MapOwned) {
+                    #(#fold_methods)*
+                }
+            });
+        }
+
+        if derives.contains(&SupportedDerives::Map) {
+            let mut handle_generics = c_generics_no_child.clone();
+            handle_generics.params.insert(0, syn::parse2(quote! { 'store }).unwrap());
+            let (impl_generics, _, _) = handle_generics.split_for_impl();
+            let (_, ty_generics, _) = trait_generics.split_for_impl();
+            let c_args = generic_args(&c_generics_no_child);
+            // both methods map over a borrowed node, which clones fields that
+            // are not children
+            let handle_where_clause = where_clause(clone_predicates(&c_generics, all_fields.iter(), &[&child_ident]));
+            // in a family, `as_ref` borrows the node without cloning its
+            // children of other types, so that ids are borrowed twice
+            let (node, self_node, id, copied_id) = if sorts.is_empty() {
+                (quote! { node }, quote! { self.node() }, quote! { &entish::NodeId }, quote! { *id })
+            } else {
+                (
+                    quote! { &node.as_refs() },
+                    quote! { &self.node().as_refs() },
+                    quote! { &&entish::NodeId },
+                    quote! { **id }
+                )
+            };
+
+            // the handles to trees whose nodes are stored with ids as children
+            let handles = [
+                (quote! { entish::arena::ArenaRef }, quote! { arena }),
+                (quote! { entish::intern::Interned }, quote! { interner })
+            ];
+            for (handle, store) in handles.iter() {
                 out.extend(quote! {
-                    impl<#map_output_ident, #map_owned_generic_params>
-                        entish::MapOwned<#child_ident, #map_output_ident>
-                        for #c_ident#c_generics
-                        #c_where_clause
+                    impl#impl_generics #trait_ident#ty_generics
+                        for #handle<'store, #c_ident<#(#c_args,)* entish::NodeId>>
+                        #handle_where_clause
                     {
-                        type OuterO = #c_ident#mapped_c_generics;
-                        fn map_owned<F>(self, f: &mut F) -> Self::OuterO
-                        where
-                            F: FnMut(#child_ident) -> #map_output_ident
-                        {
-                            match self {
-                                #map_variants_owned
-                            }
+                        #(type #sort_params = #sort_params;)*
+
+                        fn as_ref(&self) -> #c_ident<#c_generics_with_ref_self> {
+                            let mut children = self
+                                .children_with(|node| {
+                                    let mut ids = Vec::new();
+                                    entish::Map::map(#node, &mut |id: #id| ids.push(#copied_id));
+                                    ids
+                                })
+                                .iter();
+                            entish::Map::map(#self_node, &mut |_: #id| children.next().unwrap())
+                        }
+
+                        fn into_inner(self) -> #c_ident<#(#c_args,)* Self> {
+                            let #store = self.#store();
+                            entish::Map::map(self.node(), &mut |id: &entish::NodeId| #store.tree(*id))
                         }
                     }
                 });
             }
         }
 
-        if derives.contains(&SupportedDerives::IntoOption) {
-            let generic_args: Punctuated<TokenStream, Comma> = c_generics
-                .params
-                .iter()
-                .cloned()
-                .map(|param| {
-                    if param == generic_param!(format_ident!("{}", CHILD)) {
-                        quote! { Option<#param> }
-                    } else {
-                        quote! { #param }
-                    }
-                })
-                .collect();
+        if let Some(tree) = &tree {
+            if !derives.contains(&SupportedDerives::Map) || !derives.contains(&SupportedDerives::MapOwned) {
+                return Err(Error::new_spanned(
+                    tree,
+                    "generating a tree requires `#[derive(Map, MapOwned)]`"
+                ))
+            }
 
-            let mapped_variants: Punctuated<TokenStream, Comma> = variants
-                .iter()
-                .map(|variant| {
-                    let ident = &variant.ident;
-                    quote! { Self::#ident(variant) => variant.into_option()?.into() }
+            // in a family, the children of other types are their trees,
+            // behind the same indirection as the children of this type
+            let mut tree_generics = c_generics_no_child.clone();
+            tree_generics.params = tree_generics.params
+                .into_iter()
+                .filter(|gp| match gp {
+                    GenericParam::Type(TypeParam { ident, .. }) => sorts.iter().all(|sort| sort.param != *ident),
+                    _ => true
                 })
                 .collect();
+            let (impl_generics, ty_generics, _) = tree_generics.split_for_impl();
+            let sort_trees: Vec<&Ident> = sorts.iter()
+                .map(|sort| sort.tree.as_ref().expect("checked by `family`"))
+                .collect();
+            let sort_args = |child: &dyn Fn(&Ident) -> TokenStream| -> Vec<TokenStream> {
+                generic_args(&c_generics_no_child)
+                    .into_iter()
+                    .zip(c_generics_no_child.params.iter())
+                    .map(|(arg, gp)| match gp {
+                        GenericParam::Type(TypeParam { ident, .. }) => sorts
+                            .iter()
+                            .zip(sort_trees.iter())
+                            .find(|(sort, _)| sort.param == *ident)
+                            .map_or(arg, |(_, sort_tree)| child(sort_tree)),
+                        _ => arg
+                    })
+                    .collect()
+            };
+            // the arguments of the node wrapped by the tree, and of the nodes
+            // whose children are trees
+            let c_args = sort_args(&|sort_tree| quote! { #indirection<#sort_tree> });
+            let node_args = sort_args(&|sort_tree| quote! { #sort_tree });
+            let tree_where_clause = quote! {
+                where
+                    #indirection<Self>: entish::Indirection<Self>,
+                    #(#indirection<#sort_trees>: entish::Indirection<#sort_trees>,)*
+            };
+            // `as_ref` maps over a borrowed node, which clones fields that
+            // are not children
+            let clone_predicates: Vec<TokenStream> = clone_predicates(&c_generics, all_fields.iter(), &child_idents)
+                .into_iter()
+                .collect();
 
-            out.extend(quote! {
-                impl#c_generics
-                    entish::IntoOption<#c_ident#c_generics>
-                    for #c_ident<#generic_args>
-                {
-                    fn into_option(self) -> Option<#c_ident#c_generics> {
-                        Some(
-                            match self {
-                                #mapped_variants
-                            }
+            let tree_doc = format!(
+                "A tree whose nodes are [{c_ident}](enum.{c_ident}.html), with children behind a `{indirection}`.",
+                c_ident = c_ident,
+                indirection = quote! { #indirection }
+            );
+
+            // `node`, whose children of all types are put behind the
+            // indirection
+            let new_node = sorts.iter().zip(sort_trees.iter()).fold(
+                quote! {
+                    entish::MapOwned::map_owned(
+                        node,
+                        &mut <#indirection<Self> as entish::Indirection<Self>>::new
+                    )
+                },
+                |node, (sort, sort_tree)| {
+                    let map_owned_sort = sort.method_ident("map_owned");
+                    quote! {
+                        #node.#map_owned_sort(
+                            &mut <#indirection<#sort_tree> as entish::Indirection<#sort_tree>>::new
                         )
                     }
                 }
-            });
-        }
-
-        if derives.contains(&SupportedDerives::IntoResult) {
-            let err_tp: TypeParam = syn::parse2(quote! { __Error }).unwrap();
+            );
 
-            let mut c_generics_with_e = c_generics.clone();
-            c_generics_with_e.params.push(GenericParam::Type(err_tp.clone()));
+            out.extend(quote! {
+                #[doc = #tree_doc]
+                #(#derive_attributes)*
+                pub struct #tree#tree_generics(pub #c_ident<#(#c_args,)* #indirection<Self>>);
+            });
 
-            let generic_args: Punctuated<TokenStream, Comma> = c_generics
-                .params
-                .iter()
-                .cloned()
-                .map(|param| {
-                    if param == generic_param!(format_ident!("{}", CHILD)) {
-                        quote! { std::result::Result<#param, #err_tp> }
+            // children of other types are unwrapped from their indirection
+            // too, after the ones of type `Self`
+            let (as_ref, into_inner) = sorts.iter().zip(sort_trees.iter()).fold(
+                (
+                    if sorts.is_empty() {
+                        quote! { entish::Map::map(&self.0, &mut <#indirection<Self> as std::ops::Deref>::deref) }
                     } else {
-                        quote! { #param }
+                        quote! {
+                            entish::MapOwned::map_owned(
+                                self.0.as_refs(),
+                                &mut <#indirection<Self> as std::ops::Deref>::deref
+                            )
+                        }
+                    },
+                    quote! {
+                        entish::MapOwned::map_owned(
+                            self.0,
+                            &mut <#indirection<Self> as entish::Indirection<Self>>::into_inner
+                        )
                     }
-                })
-                .collect();
-
-            let mapped_variants: Punctuated<TokenStream, Comma> = variants
-                .iter()
-                .map(|variant| {
-                    let ident = &variant.ident;
-                    quote! { Self::#ident(variant) => variant.into_result()?.into() }
-                })
-                .collect();
+                ),
+                |(as_ref, into_inner), (sort, sort_tree)| {
+                    let map_owned_sort = sort.method_ident("map_owned");
+                    (
+                        quote! {
+                            #as_ref.#map_owned_sort(&mut <#indirection<#sort_tree> as std::ops::Deref>::deref)
+                        },
+                        quote! {
+                            #into_inner.#map_owned_sort(
+                                &mut <#indirection<#sort_tree> as entish::Indirection<#sort_tree>>::into_inner
+                            )
+                        }
+                    )
+                }
+            );
+            let sort_params = sorts.iter().map(|sort| &sort.param);
 
             out.extend(quote! {
-                impl#c_generics_with_e
-                    entish::IntoResult<#c_ident#c_generics, #err_tp>
-                    for #c_ident<#generic_args>
+                impl#impl_generics #trait_ident#ty_generics for #tree#ty_generics
+                    #tree_where_clause
+                    #(#clone_predicates,)*
                 {
-                    fn into_result(self) -> std::result::Result<#c_ident#c_generics, #err_tp> {
-                        Ok(
-                            match self {
-                                #mapped_variants
-                            }
-                        )
+                    #(type #sort_params = #sort_trees;)*
+
+                    fn as_ref(&self) -> #c_ident<#c_generics_with_ref_self> {
+                        #as_ref
+                    }
+
+                    fn into_inner(self) -> #c_ident<#c_generics_with_self> {
+                        #into_inner
                     }
                 }
             });
-        }
-
-        let mut c_generics_no_child = c_generics.clone();
-        c_generics_no_child.params.pop();
 
-        let c_where_clause = where_clause_for_generics(c_generics_no_child.params.iter());
-
-        let mut c_generics_with_o = c_generics_no_child.clone();
-        c_generics_with_o.params.push(generic_param!(format_ident!("O")));
+            out.extend(quote! {
+                impl#impl_generics #tree#ty_generics
+                    #tree_where_clause
+                {
+                    /// Make a tree whose root is `node`
+                    pub fn new(node: #c_ident<#(#node_args,)* Self>) -> Self {
+                        Self(#new_node)
+                    }
 
-        let mut c_generics_with_ref_self: Punctuated<TokenStream, Comma> = c_generics_no_child
-            .clone()
-            .params
-            .into_iter()
-            .map(|c| quote! { #c })
-            .collect();
-        c_generics_with_ref_self.push(quote! { &Self });
+                    #(#constructors)*
+                }
 
-        let mut c_generics_with_self = c_generics_no_child.clone();
-        c_generics_with_self.params.push(generic_param!(format_ident!("Self")));
+                impl#impl_generics std::ops::Deref for #tree#ty_generics {
+                    type Target = #c_ident<#(#c_args,)* #indirection<Self>>;
+                    fn deref(&self) -> &Self::Target {
+                        &self.0
+                    }
+                }
 
-        let maybe_try_fold_impl = if derives.contains(&SupportedDerives::IntoResult) {
-            Some(quote! {
-                /// Like `try_fold` but when the operation can fail
-                fn try_fold<F, O, E>(self, f: &mut F) -> std::result::Result<O, E>
-                where
-                    F: FnMut(#c_ident#c_generics_with_o) -> std::result::Result<O, E>
+                impl#impl_generics From<#c_ident<#(#node_args,)* Self>> for #tree#ty_generics
+                    #tree_where_clause
                 {
-                    let arg = self.into_inner()
-                        .map_owned(&mut |c| c.try_fold(f))
-                        .into_result()?;
-                    f(arg)
+                    fn from(node: #c_ident<#(#node_args,)* Self>) -> Self {
+                        Self::new(node)
+                    }
                 }
-            })
-        } else {
-            None
-        };
-
-        let trait_doc = format!(
-            "A trait for types that are like a tree whose nodes are described by [{c_ident}](enum.{c_ident}.html).",
-            c_ident = c_ident
-        );
-
-        out.extend(quote! {
-            #[doc = #trait_doc]
-            pub trait #trait_ident#c_generics_no_child: Sized
-                #c_where_clause
-            {
-                /// Unravel a node whose children are references to my
-                /// children
-                fn as_ref(&self) -> #c_ident<#c_generics_with_ref_self>;
+            });
 
-                fn into_inner(self) -> #c_ident#c_generics_with_self;
+            if derives.contains(&SupportedDerives::MapMut) {
+                out.extend(quote! {
+                    impl#impl_generics entish::MapMut<Self> for #tree#ty_generics
+                        #tree_where_clause
+                    {
+                        fn map_mut<'a, F>(&'a mut self, f: &mut F)
+                        where
+                            F: FnMut(&'a mut Self),
+                            Self: 'a
+                        {
+                            entish::MapMut::map_mut(
+                                &mut self.0,
+                                &mut |c: &'a mut #indirection<Self>| f(entish::Indirection::make_mut(c))
+                            )
+                        }
+                    }
+                });
+            }
 
-                /// Reduce the tree to a single value using by folding a
-                /// closure, recursively reducing from leaves to root
-                fn fold<F, O>(self, f: &mut F) -> O
-                where
-                    F: FnMut(#c_ident#c_generics_with_o) -> O
-                {
-                    let arg = self.into_inner().map_owned(&mut |c| c.fold(f));
-                    f(arg)
+            if !family.is_empty() {
+                let outputs: Vec<Ident> = family.iter().map(|sort| format_ident!("{}Output", sort.ident)).collect();
+                let fns: Vec<Ident> = family.iter().map(|sort| format_ident!("{}Fn", sort.ident)).collect();
+                let closures: Vec<Ident> = family.iter()
+                    .map(|sort| format_ident!("on_{}", snake_name(&sort.ident)))
+                    .collect();
+                let bounds = family.iter().zip(fns.iter()).zip(outputs.iter()).map(|((sort, f), output)| {
+                    let ident = &sort.ident;
+                    let args = family.iter()
+                        .zip(outputs.iter())
+                        .filter(|(other, _)| sort.mentions.contains(&other.ident))
+                        .map(|(_, output)| output);
+                    quote! { #f: FnMut(#ident<#(#args,)* #output>) -> #output }
+                });
+                let idx = family.iter()
+                    .position(|sort| sort.ident == c_ident)
+                    .expect("a tree is in its family");
+                let output = &outputs[idx];
+
+                // trees, shapes and outputs of all the types of the family
+                // are folded together, as the variants of a sum type declared
+                // along with the first tree
+                let family_ident = format_ident!("__{}Family", family[0].ident);
+                let sort_idents: Vec<&Ident> = family.iter().map(|sort| &sort.ident).collect();
+                if idx == 0 {
+                    let params = family.iter().map(|sort| format_ident!("__{}", sort.ident));
+                    let variants = sort_idents.iter().zip(params.clone()).map(|(ident, param)| quote! { #ident(#param) });
+                    out.extend(quote! {
+                        /// A tree, the shape of a node or an output of any
+                        /// type of the family, in `fold_family`
+                        enum #family_ident<#(#params),*> {
+                            #(#variants,)*
+                        }
+                    });
                 }
+                let family_trees = family.iter().map(|sort| sort.tree.as_ref().expect("checked by `family`"));
+                let shapes = family.iter().map(|sort| {
+                    let ident = &sort.ident;
+                    let units = sort.mentions.iter().map(|_| quote! { () });
+                    quote! { #ident<#(#units,)* ()> }
+                });
+                let (unfold_arms, build_arms): (Vec<TokenStream>, Vec<TokenStream>) = family.iter().zip(closures.iter()).map(|(sort, on_sort)| {
+                    let ident = &sort.ident;
+                    let indirection = &sort.indirection;
+                    // the children of each type, in the order of the
+                    // parameters of the node, after the ones of its own type
+                    let children = family.iter().filter(|other| sort.mentions.contains(&other.ident));
+                    let (unfold_stmts, build_stmts): (Vec<TokenStream>, Vec<TokenStream>) = children.map(|other| {
+                        let (other_ident, other_tree) = (&other.ident, other.tree.as_ref().expect("checked by `family`"));
+                        let map_owned_other = other.method_ident("map_owned");
+                        (
+                            quote! {
+                                let shape = shape.#map_owned_other(&mut |child: #indirection<#other_tree>| {
+                                    children.push(#family_ident::#other_ident(
+                                        <#indirection<#other_tree> as entish::Indirection<#other_tree>>::into_inner(child)
+                                    ))
+                                });
+                            },
+                            quote! {
+                                let node = node.#map_owned_other(&mut |()| match outputs.next() {
+                                    Some(#family_ident::#other_ident(output)) => output,
+                                    _ => unreachable!("the outputs are in the order of the children")
+                                });
+                            }
+                        )
+                    }).unzip();
+                    let tree = sort.tree.as_ref().expect("checked by `family`");
+                    (
+                        quote! {
+                            #family_ident::#ident(tree) => {
+                                let mut children = Vec::new();
+                                let shape = entish::MapOwned::map_owned(tree.0, &mut |child: #indirection<#tree>| {
+                                    children.push(#family_ident::#ident(
+                                        <#indirection<#tree> as entish::Indirection<#tree>>::into_inner(child)
+                                    ))
+                                });
+                                #(#unfold_stmts)*
+                                (#family_ident::#ident(shape), children)
+                            }
+                        },
+                        quote! {
+                            #family_ident::#ident(shape) => {
+                                let mut outputs = outputs.into_iter();
+                                let node = entish::MapOwned::map_owned(shape, &mut |()| match outputs.next() {
+                                    Some(#family_ident::#ident(output)) => output,
+                                    _ => unreachable!("the outputs are in the order of the children")
+                                });
+                                #(#build_stmts)*
+                                #family_ident::#ident(#on_sort(node))
+                            }
+                        }
+                    )
+                }).unzip();
 
-                #maybe_try_fold_impl
+                out.extend(quote! {
+                    impl#impl_generics #tree#ty_generics
+                        #tree_where_clause
+                    {
+                        /// Reduce the tree to a single value by folding a
+                        /// closure per type of node of its family, reducing
+                        /// from leaves to root. This does not recurse, so it
+                        /// works on trees of any depth.
+                        pub fn fold_family<#(#outputs,)* #(#fns),*>(self, #(#closures: &mut #fns),*) -> #output
+                        where
+                            #(#bounds,)*
+                        {
+                            let output = entish::fold::fold(
+                                #family_ident::#c_ident(self),
+                                &mut |tree: #family_ident<#(#family_trees),*>| match tree {
+                                    #(#unfold_arms,)*
+                                },
+                                &mut |shape: #family_ident<#(#shapes),*>, outputs: Vec<#family_ident<#(#outputs),*>>| {
+                                    match shape {
+                                        #(#build_arms,)*
+                                    }
+                                }
+                            );
+                            match output {
+                                #family_ident::#c_ident(output) => output,
+                                _ => unreachable!("the root is of this type")
+                            }
+                        }
+                    }
+                });
+            }
 
-                /// Get an iterator over references to children of this node
-                fn iter_children<'a, I>(&'a self) -> std::vec::IntoIter<&'a Self>
-                {
-                    let mut children = Vec::new();
-                    self.as_ref().map(&mut |&c| children.push(c));
-                    children.into_iter()
-                }
+            let trait_path = quote! { <Self as #trait_ident#ty_generics> };
+            for tree_derive in tree_derives.iter() {
+                let (predicate, body) = match tree_derive.to_string().as_str() {
+                    "PartialEq" => (
+                        quote! { PartialEq },
+                        quote! {
+                            fn eq(&self, other: &Self) -> bool {
+                                #trait_path::tree_eq(self, other)
+                            }
+                        }
+                    ),
+                    "Eq" => (quote! { Eq }, quote! {}),
+                    "PartialOrd" => (
+                        quote! { PartialOrd },
+                        quote! {
+                            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                                #trait_path::tree_partial_cmp(self, other)
+                            }
+                        }
+                    ),
+                    "Ord" => (
+                        quote! { Ord },
+                        quote! {
+                            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                                #trait_path::tree_cmp(self, other)
+                            }
+                        }
+                    ),
+                    "Hash" => (
+                        quote! { std::hash::Hash },
+                        quote! {
+                            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                                #trait_path::tree_hash(self, state)
+                            }
+                        }
+                    ),
+                    _ => unreachable!("checked when parsing `derive_tree`")
+                };
+                out.extend(quote! {
+                    impl#impl_generics #predicate for #tree#ty_generics
+                        #tree_where_clause
+                        #(#clone_predicates,)*
+                        #shape: #predicate
+                    {
+                        #body
+                    }
+                });
             }
-        });
+        }
 
         let mut example_generics: Vec<String> = c_generics_no_child
             .params
@@ -769,29 +3729,40 @@ impl EntishBuilder {
                 _ => None
             })
             .collect();
-        example_generics.push(format!("Box<Self>"));
+        example_generics.push("Box<Self>".to_string());
         let example_generics_ = example_generics.as_slice().join(", ");
         let c_ident_doc = format!(
             "A node in a tree [{trait_}](trait.{trait_}.html) whose children are of type `{child}`.
 
 It can be made into an actual tree by replacing `Child` by a type implementing [{trait_}](trait.{trait_}.html). This could be done by simply adding some recursive dynamic indirection such as
-```
+```ignore
 pub struct My{trait_}({container}<{generics}>);
 ```
+or by having one generated with `#[entish(tree = \"My{trait_}\")]`.
 ",
             trait_ = trait_ident,
             child = child_ident,
             container = c_ident,
             generics = example_generics_
         );
+        let item = match variants.first() {
+            Some(Variant { fields, .. }) if is_struct => match fields {
+                Fields::Named(named) => quote! { pub struct #c_ident#c_generics #named },
+                Fields::Unnamed(unnamed) => quote! { pub struct #c_ident#c_generics #unnamed; },
+                Fields::Unit => quote! { pub struct #c_ident#c_generics; }
+            },
+            _ => quote! {
+                pub enum #c_ident#c_generics {
+                    #variants
+                }
+            }
+        };
         out.extend(quote! {
             #[doc = #c_ident_doc]
             #attributes
-            pub enum #c_ident#c_generics {
-                #variants
-            }
+            #item
         });
 
-        out
+        Ok(out)
     }
 }
@@ -1,13 +1,9 @@
-#![feature(try_blocks)]
-
 extern crate proc_macro;
 #[macro_use] extern crate quote;
 
 //use proc_macro2::{TokenStream};
 use proc_macro::TokenStream;
-use syn::{fold::Fold, parse, parse_macro_input, ExprMatch, DeriveInput, Data, DataEnum, ExprTuple, ExprLit, Lit};
-
-use syn::{Pat, PatPath, Path};
+use syn::{parse::{Parse, ParseStream}, parse_macro_input, ExprMatch, DeriveInput, Data, DataEnum, DataStruct, DataUnion, ExprTuple, ExprLit, Lit, Error};
 
 mod entish;
 use entish::EntishBuilder;
@@ -24,39 +20,117 @@ mod utils;
 ///   [generate!](macro.generate.html)
 #[proc_macro]
 pub fn entish(input: TokenStream) -> TokenStream {
-    let out: syn::parse::Result<_> = parse::<ExprMatch>(input.clone())
-        .map(|_| expr_match(input.clone()))
-        .or_else(|_| {
-            parse::<DeriveInput>(input.clone())?;
-            Ok(generate(input))
-        });
-    out.expect("unsupported use of `entish`")
+    // skip the attributes of a `match`, as in `#[entish(..)] match ..`
+    let mut tokens = input.clone().into_iter().peekable();
+    while let Some(proc_macro::TokenTree::Punct(punct)) = tokens.peek() {
+        if punct.as_char() != '#' {
+            break
+        }
+        tokens.next();
+        tokens.next();
+    }
+    let is_match = match tokens.next() {
+        Some(proc_macro::TokenTree::Ident(ident)) => ident.to_string() == "match",
+        _ => false
+    };
+    if is_match {
+        expr_match(input)
+    } else {
+        generate(input)
+    }
 }
 
-/// Runs codegeneration on the tree declared inside.
+/// Runs codegeneration on the tree declared inside, by an enum with a
+/// variant per kind of node, or by a struct for trees with a single kind of
+/// node, such as `struct Block { stmts: Vec<Self>, label: String }`.
+///
+/// Children are fields of type `Self`, or are held in tuples, arrays and
+/// containers with a single type argument holding them, nested as needed,
+/// such as `Vec<Option<Self>>` or `BTreeMap<String, Self>`, of which they
+/// can only be the values. They cannot be held in sets. Containers of other
+/// crates, such as `SmallVec`, can hold children through a newtype
+/// implementing the traits of `entish`.
+///
+/// Several enums and structs declared in the same block make a family of
+/// trees whose nodes can also have children of each other's types, such as
+/// `Block(Vec<Stmt>)` in an `Expr`. Children of type `Stmt` are replaced by a
+/// type parameter `StmtChild`, just before `Child`, and are mapped with
+/// `map_stmt`, `map_owned_stmt`, `map_mut_stmt`, `try_traverse_stmt` and
+/// `traverse_stmt` when the corresponding traits are derived. `Zip` only
+/// pairs up children of type `Self`, and compares the others like labels.
+/// The `*Tree` trait has an associated type `StmtChild` instead, and `as_ref`
+/// borrows these children along with the ones of type `Self`. The trees of a
+/// family cannot have generic parameters.
 /// # Options
-/// ???
+/// Options are given in an `#[entish(..)]` attribute on the enum or struct:
+/// - `variants_as_structs`: declare a struct for each variant and replace
+///   the variant by an unnamed variant wrapping it. Otherwise, variants are
+///   kept as declared and everything is generated on the enum directly.
+///   Only applies to enums.
+/// - `tree = "Name"`: declare a tuple struct `Name` wrapping a node whose
+///   children are `Box<Name>`s, implementing the generated `*Tree` trait. It
+///   comes with a constructor per variant (in snake case, with a trailing
+///   underscore for `crate_`, `self_` and `super_`), `Deref` to the node
+///   and `From` a node whose children are `Name`s. Requires
+///   `#[derive(Map, MapOwned)]`. With `MapMut` also derived, the tree
+///   implements `MapMut` over its children. In a family, either all trees or
+///   none declare `tree`: each holds the others' trees as children, and gets
+///   `fold_family`, which folds it with a closure per type of node.
+/// - `indirection = "Path"`: the pointer type used between a node and its
+///   children by `tree`, which must implement `entish::Indirection`. Defaults
+///   to `Box`.
+/// - `derive_tree(PartialEq, Eq, Hash, PartialOrd, Ord)`: implement these
+///   std traits on `tree` through the stack-safe `tree_eq`, `tree_hash`,
+///   `tree_partial_cmp` and `tree_cmp`, rather than deriving them
+///   recursively. Each requires the same derive on the enum.
 #[proc_macro]
 pub fn generate(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
+    let Items(inputs) = parse_macro_input!(input as Items);
 
-    let mut builder = EntishBuilder::new(&input);
+    let out: syn::Result<_> = inputs
+        .iter()
+        .map(EntishBuilder::new)
+        .collect::<syn::Result<Vec<_>>>()
+        .and_then(|builders| {
+            let family = entish::family(&inputs, &builders)?;
+            let mut out = proc_macro2::TokenStream::new();
+            for (mut builder, input) in builders.into_iter().zip(inputs.iter()) {
+                builder.join_family(&family)?;
+                match &input.data {
+                    Data::Enum(DataEnum { variants, .. }) => {
+                        utils::collect_errors(variants.iter().map(|variant| {
+                            builder.add_node(variant).map(|_| ())
+                        }))?;
+                    },
+                    Data::Struct(DataStruct { fields, .. }) => {
+                        builder.add_struct(fields)?;
+                    },
+                    Data::Union(DataUnion { union_token, .. }) => {
+                        return Err(Error::new_spanned(union_token, "entish can only be generated from an enum or a struct"))
+                    }
+                };
+                out.extend(builder.generate()?);
+            }
+            Ok(out)
+        });
 
-    let data = input.data;
+    out.unwrap_or_else(|err| err.to_compile_error()).into()
+}
 
-    match data {
-        Data::Enum(DataEnum { variants, .. }) => {
-            for variant in variants.iter() {
-                builder.add_node(variant);
-            }
-        },
-        _ => panic!("entish can only be generated from an enum")
-    };
+/// The enums and structs declared in a single `entish!` block
+struct Items(Vec<DeriveInput>);
 
-    builder.generate().into()
+impl Parse for Items {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut items = vec![input.parse()?];
+        while !input.is_empty() {
+            items.push(input.parse()?);
+        }
+        Ok(Self(items))
+    }
 }
 
-use syn::{Expr, token::Comma, punctuated::Punctuated};
+use syn::Expr;
 
 /// `unravel(tree, n)` takes a `tree: &Tree` (where `Tree` is generated by Entish)
 /// and recursively applies `.as_ref()` to self *and then* its children a total of n times.
@@ -64,15 +138,37 @@ use syn::{Expr, token::Comma, punctuated::Punctuated};
 pub fn unravel(input: TokenStream) -> TokenStream {
     let input: proc_macro2::TokenStream = input.into();
     let input = quote! { (#input) };
-    let mut input: ExprTuple = syn::parse2(input).unwrap();
+    let mut input: ExprTuple = match syn::parse2(input) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into()
+    };
 
-    let n: u32 = match input.elems.pop().expect("an n-fold").into_value() {
-        Expr::Lit(ExprLit { lit: Lit::Int(lit_int), .. }) => {
-            lit_int.base10_digits().parse().unwrap()
+    let n: u32 = match input.elems.pop().map(|pair| pair.into_value()) {
+        Some(Expr::Lit(ExprLit { lit: Lit::Int(lit_int), .. })) => {
+            match lit_int.base10_parse() {
+                Ok(n) => n,
+                Err(err) => return err.to_compile_error().into()
+            }
+        },
+        Some(expr) => {
+            return Error::new_spanned(expr, "n-fold param should be int literal")
+                .to_compile_error()
+                .into()
         },
-        _ => panic!("n-fold param should be int literal")
+        None => {
+            return Error::new(proc_macro2::Span::call_site(), "expected `unravel!(tree, n)`")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let tree = match input.elems.pop() {
+        Some(pair) => pair.into_value(),
+        None => {
+            return Error::new(proc_macro2::Span::call_site(), "expected `unravel!(tree, n)`")
+                .to_compile_error()
+                .into()
+        }
     };
-    let tree = input.elems.pop().expect("an expr").into_value();
 
     let out = match n {
         0 => quote! { #tree },
@@ -90,30 +186,122 @@ pub fn unravel(input: TokenStream) -> TokenStream {
 }
 
 /// Helper for matching on Entish generated trees.
+///
+/// Takes a `match` expression whose scrutinee is a value implementing the
+/// `*Tree` trait generated by Entish, and whose arms are patterns on the node
+/// enum. Node patterns can be nested in the place of children, in which case
+/// the corresponding child is unravelled with `.as_ref()` and matched in turn.
+/// A child can be bound whole with `name @ Node::Variant(..)`. Arms are tried
+/// in order, so an arm whose nested patterns do not match falls through to the
+/// next one. The guard of an arm is evaluated once per alternative that is
+/// tried, as for a plain `match`.
+///
+/// `#[entish(max_depth = N)]` on the `match` rejects node patterns nested
+/// more than `N` levels deep below the patterns of the arms.
+/// ```
+/// #[macro_use] extern crate entish;
+/// use entish::prelude::*;
+///
+/// entish! {
+///     #[derive(Map, MapOwned)]
+///     #[entish(tree = "Expr", variants_as_structs)]
+///     enum Arithmetic {
+///         Plus {
+///             left: Self,
+///             right: Self
+///         },
+///         Times {
+///             left: Self,
+///             right: Self
+///         },
+///         Just(i32)
+///     }
+/// }
+///
+/// /// Drop the neutral literal on the left of the root.
+/// fn simplify(expr: &Expr) -> &Expr {
+///     expr_match! {
+///         match expr {
+///             Arithmetic::Plus(Plus { left: Arithmetic::Just(Just(0)), right }) => right,
+///             Arithmetic::Times(Times { left: Arithmetic::Just(Just(1)), right }) => right,
+///             _ => expr
+///         }
+///     }
+/// }
+///
+/// /// The sum of two literals, if it is a single digit.
+/// fn digit_sum(expr: &Expr) -> Option<i32> {
+///     expr_match! {
+///         #[entish(max_depth = 1)]
+///         match expr {
+///             Arithmetic::Plus(Plus {
+///                 left: Arithmetic::Just(Just(left)),
+///                 right: Arithmetic::Just(Just(right))
+///             }) if left + right < 10 => Some(left + right),
+///             _ => None
+///         }
+///     }
+/// }
+///
+/// # fn main() {
+/// let two = Expr::plus(Expr::just(0), Expr::just(2));
+/// assert!(matches!(simplify(&two).as_ref(), Arithmetic::Just(Just(2))));
+/// assert_eq!(Some(2), digit_sum(&two));
+/// assert_eq!(None, digit_sum(&Expr::plus(Expr::just(5), Expr::just(5))));
+/// assert_eq!(None, digit_sum(&Expr::times(Expr::just(1), Expr::just(2))));
+/// # }
+/// ```
+/// Patterns nested deeper than `max_depth` do not compile:
+/// ```compile_fail
+/// # #[macro_use] extern crate entish;
+/// # use entish::prelude::*;
+/// # entish! {
+/// #     #[derive(Map, MapOwned)]
+/// #     #[entish(tree = "Expr", variants_as_structs)]
+/// #     enum Arithmetic {
+/// #         Plus {
+/// #             left: Self,
+/// #             right: Self
+/// #         },
+/// #         Just(i32)
+/// #     }
+/// # }
+/// fn zero_plus_zero(expr: &Expr) -> bool {
+///     expr_match! {
+///         #[entish(max_depth = 1)]
+///         match expr {
+///             Arithmetic::Plus(Plus {
+///                 left: Arithmetic::Plus(Plus { left: Arithmetic::Just(Just(0)), .. }),
+///                 ..
+///             }) => true,
+///             _ => false
+///         }
+///     }
+/// }
+/// # fn main() {}
+/// ```
 #[proc_macro]
 pub fn expr_match(input: TokenStream) -> TokenStream
 {
-    unimplemented!();
-
     let mut input = parse_macro_input!(input as ExprMatch);
+    let max_depth = match pattern::max_depth_of(&mut input.attrs) {
+        Ok(max_depth) => max_depth,
+        Err(err) => return err.to_compile_error().into()
+    };
 
-    let mut path_pats = input.arms
+    let node_path = input.arms
         .iter()
-        .filter_map(|arm| match &arm.pat {
-            Pat::Path(PatPath { path, .. }) => Some(path),
-            _ => None
-        });
-
-    if let Some(path) = path_pats.next() {
-        let mut node_path = path.clone();
-        node_path
-            .segments
-            .pop()
-            .expect("top-level path pattern should match on variant of node");
+        .find_map(|arm| pattern::node_path_of(&arm.pat));
 
-        let mut builder = PatternBuilder::new(node_path);
-        input = builder.fold_expr_match(input);
+    match node_path {
+        Some(node_path) => {
+            let mut builder = PatternBuilder::new(node_path).with_max_depth(max_depth);
+            builder.build(input).unwrap_or_else(|err| err.to_compile_error()).into()
+        },
+        None => {
+            let expr = &input.expr;
+            *input.expr = syn::parse2(quote! { (#expr).as_ref() }).unwrap();
+            (quote! { #input }).into()
+        }
     }
-
-    (quote! { #input }).into()
 }
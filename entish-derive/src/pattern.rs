@@ -1,18 +1,424 @@
-use syn::{Path, ExprMatch, fold::{Fold, fold_expr_match}};
+use proc_macro2::{Ident, TokenStream};
+use syn::{
+    Arm, Attribute, Error, Expr, ExprMatch, Lit, Meta, MetaList, MetaNameValue, NestedMeta, Pat, PatIdent,
+    PatOr, PatTuple, Path, fold::{Fold, fold_pat}
+};
 
+use crate::utils::collect_errors;
+
+/// The variable holding which alternative of an arm with nested node
+/// patterns matched, so that its guard is only evaluated once
+const ALTERNATIVE: &str = "__entish_alternative";
+
+/// Rewrites a `match` over a tree written with nested node patterns into a
+/// `match` over `.as_ref()` of the tree, where every nested node pattern is
+/// replaced by a binding and matched, in turn, against `.as_ref()` of that
+/// binding.
+///
+/// Because a nested match can fail after its parent pattern has already
+/// matched, nested patterns are checked in the arm's guard and then matched
+/// again in its body. This keeps the semantics of falling through to the next
+/// arm. When they have several alternatives, the check records which one
+/// matched, and the body matches that one without evaluating the guard again.
 pub struct PatternBuilder {
     node_path: Path,
-    max_depth: Option<u16>
+    /// How deep node patterns can be nested, if limited
+    max_depth: Option<u16>,
+    depth: u16,
+    children: Vec<(Ident, Pat)>,
+    count: usize,
+    /// The number of alternatives of the current arm visited so far
+    alternatives: usize,
+    /// The first node pattern nested deeper than `max_depth`
+    too_deep: Option<Error>
+}
+
+/// A pattern whose nested node patterns have been replaced by bindings.
+struct Lowered {
+    pat: Pat,
+    children: Vec<(Ident, Pat)>
+}
+
+fn path_prefix_eq(path: &Path, prefix: &Path) -> bool {
+    path.segments.len() == prefix.segments.len() + 1 &&
+        path.segments
+            .iter()
+            .zip(prefix.segments.iter())
+            .all(|(l, r)| l.ident == r.ident)
+}
+
+fn is_paren(pat_tuple: &PatTuple) -> bool {
+    pat_tuple.elems.len() == 1 && !pat_tuple.elems.trailing_punct()
+}
+
+fn unparen(pat: Pat) -> Pat {
+    match pat {
+        Pat::Tuple(mut pat_tuple) if is_paren(&pat_tuple) => {
+            unparen(pat_tuple.elems.pop().unwrap().into_value())
+        },
+        pat => pat
+    }
+}
+
+/// The path of the node enum a top-level pattern matches on, i.e. the path
+/// to one of its variants with the last segment removed.
+pub fn node_path_of(pat: &Pat) -> Option<Path> {
+    let path = match pat {
+        Pat::Path(p) => &p.path,
+        Pat::TupleStruct(p) => &p.path,
+        Pat::Struct(p) => &p.path,
+        Pat::Or(PatOr { cases, .. }) => return cases.iter().find_map(node_path_of),
+        _ => return None
+    };
+    let n_segments = path.segments.len();
+    if n_segments < 2 {
+        return None
+    }
+    let segments = path.segments.iter().take(n_segments - 1).cloned().collect();
+    Some(Path { segments, ..path.clone() })
+}
+
+/// The options of `expr_match!` given in `#[entish(..)]` attributes on the
+/// `match`, which are removed from `attrs`. The only one is `max_depth = N`,
+/// how deep node patterns can be nested.
+pub fn max_depth_of(attrs: &mut Vec<Attribute>) -> syn::Result<Option<u16>> {
+    let mut max_depth = None;
+    let mut others = Vec::new();
+    for attribute in attrs.drain(..) {
+        if !attribute.path.is_ident("entish") {
+            others.push(attribute);
+            continue
+        }
+
+        let nested = match attribute.parse_meta()? {
+            Meta::List(MetaList { nested, .. }) => nested,
+            meta => {
+                return Err(Error::new_spanned(meta, "expected a list of options as in `#[entish(..)]`"))
+            }
+        };
+        collect_errors(nested.iter().map(|nested_meta| {
+            match nested_meta {
+                NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit: Lit::Int(lit), .. }))
+                    if path.is_ident("max_depth") =>
+                {
+                    max_depth = Some(lit.base10_parse()?);
+                    Ok(())
+                },
+                _ => Err(Error::new_spanned(nested_meta, "unsupported option in `#[entish(..)]`"))
+            }
+        }))?;
+    }
+    *attrs = others;
+    Ok(max_depth)
 }
 
 impl PatternBuilder {
     pub fn new(node_path: Path) -> Self {
-        Self { node_path, max_depth: None }
+        Self {
+            node_path,
+            max_depth: None,
+            depth: 0,
+            children: Vec::new(),
+            count: 0,
+            alternatives: 0,
+            too_deep: None
+        }
+    }
+
+    /// Reject node patterns nested more than `max_depth` levels deep,
+    /// below the patterns of the arms.
+    pub fn with_max_depth(mut self, max_depth: Option<u16>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The `match` rewritten from `input`, in a block declaring what the
+    /// arms need.
+    pub fn build(&mut self, input: ExprMatch) -> syn::Result<TokenStream> {
+        let input = self.fold_expr_match(input);
+        if let Some(err) = self.too_deep.take() {
+            return Err(err)
+        }
+        let alternative = format_ident!("{}", ALTERNATIVE);
+        Ok(quote! {
+            {
+                #[allow(unused_variables)]
+                let #alternative = std::cell::Cell::new(0usize);
+                #input
+            }
+        })
+    }
+
+    fn is_node_pattern(&self, pat: &Pat) -> bool {
+        match pat {
+            Pat::Path(p) => path_prefix_eq(&p.path, &self.node_path),
+            Pat::TupleStruct(p) => path_prefix_eq(&p.path, &self.node_path),
+            Pat::Struct(p) => path_prefix_eq(&p.path, &self.node_path),
+            Pat::Or(PatOr { cases, .. }) => cases.iter().all(|case| self.is_node_pattern(case)),
+            // a parenthesized pattern
+            Pat::Tuple(pat_tuple) if is_paren(pat_tuple) => {
+                self.is_node_pattern(&pat_tuple.elems[0])
+            },
+            _ => false
+        }
+    }
+
+    fn contains_node_pattern(&self, pat: &Pat) -> bool {
+        let mut builder = Self::new(self.node_path.clone());
+        !builder.lower(pat.clone()).children.is_empty()
+    }
+
+    /// Keep an error if the node pattern `pat`, found nested in a pattern at
+    /// the current depth, is nested deeper than `max_depth`.
+    fn check_depth(&mut self, pat: &Pat) {
+        match self.max_depth {
+            Some(max_depth) if self.depth >= max_depth && self.too_deep.is_none() => {
+                self.too_deep = Some(Error::new_spanned(
+                    pat,
+                    format!("node patterns cannot be nested more than `max_depth = {}` levels deep", max_depth)
+                ))
+            },
+            _ => ()
+        }
+    }
+
+    fn fresh_ident(&mut self) -> Ident {
+        let ident = format_ident!("__entish_child_{}_{}", self.depth, self.count);
+        self.count += 1;
+        ident
+    }
+
+    /// Replace all node patterns strictly inside `pat` by bindings.
+    fn lower(&mut self, pat: Pat) -> Lowered {
+        let children = std::mem::take(&mut self.children);
+        let pat = self.lower_top(pat);
+        let children = std::mem::replace(&mut self.children, children);
+        Lowered { pat, children }
+    }
+
+    fn lower_top(&mut self, pat: Pat) -> Pat {
+        match pat {
+            Pat::Or(mut pat_or) => {
+                pat_or.cases = pat_or.cases
+                    .into_iter()
+                    .map(|case| self.lower_top(case))
+                    .collect();
+                Pat::Or(pat_or)
+            },
+            Pat::Tuple(mut pat_tuple) if is_paren(&pat_tuple) => {
+                let pat = pat_tuple.elems.pop().unwrap().into_value();
+                pat_tuple.elems.push(self.lower_top(pat));
+                Pat::Tuple(pat_tuple)
+            },
+            Pat::Ident(PatIdent { subpat: Some((at, subpat)), attrs, by_ref, mutability, ident }) => {
+                let subpat = Box::new(self.lower_top(*subpat));
+                Pat::Ident(PatIdent { subpat: Some((at, subpat)), attrs, by_ref, mutability, ident })
+            },
+            pat => fold_pat(self, pat)
+        }
+    }
+
+    /// Split a pattern in its top-level alternatives if any of them has
+    /// nested node patterns, as each of them will need its own guard.
+    fn alternatives(&self, pat: Pat) -> Vec<Pat> {
+        match unparen(pat) {
+            Pat::Or(PatOr { cases, .. }) if cases.iter().any(|case| self.contains_node_pattern(case)) => {
+                cases.into_iter().collect()
+            },
+            pat => vec![pat]
+        }
+    }
+
+    /// Lower a tuple of child patterns, one alternative per combination of
+    /// their alternatives.
+    fn lower_children(&mut self, children: &[(Ident, Pat)]) -> (TokenStream, Vec<Lowered>) {
+        let scrutinee = match children {
+            [(ident, _)] => quote! { #ident.as_ref() },
+            children => {
+                let idents = children.iter().map(|(ident, _)| ident);
+                quote! { (#(#idents.as_ref(),)*) }
+            }
+        };
+
+        let mut combinations: Vec<Vec<Pat>> = vec![Vec::new()];
+        for (_, pat) in children.iter() {
+            let alternatives = self.alternatives(pat.clone());
+            combinations = combinations
+                .into_iter()
+                .flat_map(|prefix| {
+                    alternatives.iter().map(move |alternative| {
+                        let mut combination = prefix.clone();
+                        combination.push(alternative.clone());
+                        combination
+                    })
+                })
+                .collect();
+        }
+
+        self.depth += 1;
+        let lowered = combinations
+            .into_iter()
+            .map(|combination| {
+                let lowered: Vec<Lowered> = combination
+                    .into_iter()
+                    .map(|pat| self.lower(pat))
+                    .collect();
+                let children = lowered.iter().flat_map(|l| l.children.iter().cloned()).collect();
+                let pat = match lowered.as_slice() {
+                    [Lowered { pat, .. }] => pat.clone(),
+                    lowered => {
+                        let pats = lowered.iter().map(|l| &l.pat);
+                        syn::parse2(quote! { (#(#pats,)*) }).unwrap()
+                    }
+                };
+                Lowered { pat, children }
+            })
+            .collect();
+        self.depth -= 1;
+
+        (scrutinee, lowered)
+    }
+
+    /// An expression checking that all of `children` match their patterns
+    /// and that `guard` holds, which records the alternative that did.
+    fn check(&mut self, children: &[(Ident, Pat)], guard: Option<&Expr>) -> TokenStream {
+        if children.is_empty() {
+            let alternative = format_ident!("{}", ALTERNATIVE);
+            let index = self.alternatives;
+            self.alternatives += 1;
+            let guard = match guard {
+                Some(guard) => quote! { #guard },
+                None => quote! { true }
+            };
+            return quote! {
+                {
+                    let __entish_matched = #guard;
+                    if __entish_matched {
+                        #alternative.set(#index)
+                    }
+                    __entish_matched
+                }
+            }
+        }
+
+        let (scrutinee, alternatives) = self.lower_children(children);
+        let arms: Vec<TokenStream> = alternatives
+            .into_iter()
+            .map(|Lowered { pat, children }| {
+                let check = self.check(&children, guard);
+                quote! {
+                    #[allow(unused_variables)]
+                    #pat if #check => true,
+                }
+            })
+            .collect();
+
+        quote! {
+            match #scrutinee {
+                #(#arms)*
+                #[allow(unreachable_patterns)]
+                _ => false
+            }
+        }
+    }
+
+    /// An expression binding all the variables in `children` and evaluating
+    /// to `body`. Assumes `check` has already succeeded on `children`, and
+    /// recorded which alternative did.
+    fn body(&mut self, children: &[(Ident, Pat)], body: &Expr) -> TokenStream {
+        if children.is_empty() {
+            self.alternatives += 1;
+            return quote! { #body }
+        }
+
+        let (scrutinee, alternatives) = self.lower_children(children);
+        // with a single alternative, `check` already guarantees it matches
+        let needs_check = alternatives.len() > 1;
+        let alternative = format_ident!("{}", ALTERNATIVE);
+        let arms: Vec<TokenStream> = alternatives
+            .into_iter()
+            .map(|Lowered { pat, children }| {
+                // the alternatives under this one are numbered in the same
+                // order as by `check`
+                let first = self.alternatives;
+                let inner = self.body(&children, body);
+                let last = self.alternatives;
+                if needs_check {
+                    quote! { #pat if (#first..#last).contains(&#alternative.get()) => #inner, }
+                } else {
+                    quote! { #pat => #inner, }
+                }
+            })
+            .collect();
+
+        quote! {
+            match #scrutinee {
+                #(#arms)*
+                #[allow(unreachable_patterns)]
+                _ => unreachable!()
+            }
+        }
+    }
+
+    fn build_arms(&mut self, arm: Arm) -> Vec<Arm> {
+        let guard = arm.guard.as_ref().map(|(_, guard)| &**guard);
+        self.alternatives(arm.pat.clone())
+            .into_iter()
+            .map(|pat| {
+                let Lowered { pat, children } = self.lower(pat);
+                if children.is_empty() {
+                    return Arm { pat, ..arm.clone() }
+                }
+                self.alternatives = 0;
+                let check = self.check(&children, guard);
+                self.alternatives = 0;
+                let body = self.body(&children, &arm.body);
+                let attrs = &arm.attrs;
+                syn::parse2(quote! {
+                    #(#attrs)*
+                    #pat if #check => #body,
+                }).unwrap()
+            })
+            .collect()
     }
 }
 
 impl Fold for PatternBuilder {
     fn fold_expr_match(&mut self, input: ExprMatch) -> ExprMatch {
-        fold_expr_match(self, input)
+        let ExprMatch { attrs, match_token, expr, brace_token, arms } = input;
+        let expr = syn::parse2(quote! { (#expr).as_ref() }).unwrap();
+        let arms = arms
+            .into_iter()
+            .flat_map(|arm| self.build_arms(arm))
+            .collect();
+        ExprMatch { attrs, match_token, expr, brace_token, arms }
+    }
+
+    fn fold_pat(&mut self, pat: Pat) -> Pat {
+        if !self.is_node_pattern(&pat) {
+            return fold_pat(self, pat)
+        }
+        self.check_depth(&pat);
+        let ident = self.fresh_ident();
+        self.children.push((ident.clone(), pat));
+        Pat::Ident(PatIdent {
+            attrs: Vec::new(),
+            by_ref: None,
+            mutability: None,
+            ident,
+            subpat: None
+        })
+    }
+
+    fn fold_pat_ident(&mut self, pat: PatIdent) -> PatIdent {
+        // `name @ Node::Variant(..)` binds `name` to the child itself
+        match pat.subpat {
+            Some((_, subpat)) if self.is_node_pattern(&subpat) => {
+                self.check_depth(&subpat);
+                self.children.push((pat.ident.clone(), *subpat));
+                PatIdent { subpat: None, ..pat }
+            },
+            subpat => syn::fold::fold_pat_ident(self, PatIdent { subpat, ..pat })
+        }
     }
 }
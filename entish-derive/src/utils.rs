@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use proc_macro2::Ident;
-use syn::{Variant, PathSegment, fold::{fold_ident, fold_variant, fold_path_segment, fold_type, Fold}};
+use syn::{Error, ext::IdentExt, PathSegment, Type, TypePath, fold::{fold_ident, fold_path_segment, fold_type, Fold}};
 
 #[derive(Debug, Default)]
 pub struct MentionedGenerics {
@@ -10,12 +10,6 @@ pub struct MentionedGenerics {
 }
 
 impl MentionedGenerics {
-    pub fn new<'a, I: IntoIterator<Item = &'a Ident>>(targets: I) -> Self {
-        Self {
-            targets: targets.into_iter().cloned().collect(),
-            mentioned: HashSet::new()
-        }
-    }
     pub fn into_mentioned(self) -> HashSet<Ident> {
         self.mentioned
     }
@@ -46,13 +40,38 @@ impl ReplaceIdent {
 impl Fold for ReplaceIdent {
     fn fold_ident(&mut self, mut ident: Ident) -> Ident {
         if ident == self.replace {
+            let span = ident.span();
             ident = self.with.clone();
+            ident.set_span(span);
         }
 
         fold_ident(self, ident)
     }
 }
 
+/// Replaces a type that is exactly `replace` by `with`, e.g. `Child` by
+/// `&'a Tree` in `Vec<Child>`.
+#[derive(Debug)]
+pub struct ReplaceType {
+    replace: Ident,
+    with: Type
+}
+
+impl ReplaceType {
+    pub fn replace_with(replace: Ident, with: Type) -> Self {
+        Self { replace, with }
+    }
+}
+
+impl Fold for ReplaceType {
+    fn fold_type(&mut self, ty: Type) -> Type {
+        match &ty {
+            Type::Path(TypePath { qself: None, path }) if path.is_ident(&self.replace) => self.with.clone(),
+            _ => fold_type(self, ty)
+        }
+    }
+}
+
 pub struct FindIdent {
     ident: Ident,
     matched: bool
@@ -83,19 +102,166 @@ impl FindIdent {
 use syn::{punctuated::Punctuated, token::Comma, Fields, Field, FieldsNamed, FieldsUnnamed, Index};
 use proc_macro2::TokenStream;
 
-pub(crate) fn map_fields<F>(fields: &Fields, f: F) -> TokenStream
+/// How the fields of a node are accessed from generated code.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Place {
+    /// As fields of `self`, e.g. `self.left`
+    SelfField,
+    /// As bindings by reference obtained from [destructure_fields]
+    RefBinding,
+    /// As bindings by value obtained from [destructure_fields]
+    Binding,
+    /// As bindings by reference to the fields of another node obtained from
+    /// [destructure_other_fields]
+    OtherRefBinding,
+    /// As bindings by value named after the fields, see [field_param]
+    Param
+}
+
+const SELF_PREFIX: &str = "__self";
+const OTHER_PREFIX: &str = "__other";
+
+fn field_binding(prefix: &str, idx: usize, field: &Field) -> Ident {
+    match &field.ident {
+        Some(ident) => format_ident!("{}_{}", prefix, ident),
+        None => format_ident!("{}_{}", prefix, idx)
+    }
+}
+
+/// The name of a parameter standing for a field, e.g. in the methods of the
+/// `*Visit` and `*Fold` traits: the name of the field, or `field_0` and so
+/// on for unnamed fields.
+pub(crate) fn field_param(idx: usize, field: &Field) -> Ident {
+    match &field.ident {
+        Some(ident) => ident.clone(),
+        None => format_ident!("field_{}", idx)
+    }
+}
+
+fn field_place(place: Place, idx: usize, field: &Field) -> TokenStream {
+    match place {
+        Place::SelfField => match &field.ident {
+            Some(ident) => quote! { self.#ident },
+            None => {
+                let field_index: Index = idx.into();
+                quote! { self.#field_index }
+            }
+        },
+        Place::RefBinding => {
+            let binding = field_binding(SELF_PREFIX, idx, field);
+            quote! { (*#binding) }
+        },
+        Place::Binding => {
+            let binding = field_binding(SELF_PREFIX, idx, field);
+            quote! { #binding }
+        },
+        Place::OtherRefBinding => {
+            let binding = field_binding(OTHER_PREFIX, idx, field);
+            quote! { (*#binding) }
+        },
+        Place::Param => {
+            let param = field_param(idx, field);
+            quote! { #param }
+        }
+    }
+}
+
+/// The places of all `fields`, accessed as per `place`, in order.
+pub(crate) fn field_places(fields: &Fields, place: Place) -> Vec<(TokenStream, &Field)> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| (field_place(place, idx, field), field))
+        .collect()
+}
+
+/// A pattern binding all `fields` to the places used by `map_fields` with
+/// `Place::RefBinding` (if `by_ref`) or `Place::Binding`.
+pub(crate) fn destructure_fields(fields: &Fields, by_ref: bool) -> TokenStream {
+    destructure_fields_with(SELF_PREFIX, fields, by_ref)
+}
+
+/// A pattern binding all `fields` to the places used by `map_fields` with
+/// `Place::OtherRefBinding`.
+pub(crate) fn destructure_other_fields(fields: &Fields) -> TokenStream {
+    destructure_fields_with(OTHER_PREFIX, fields, true)
+}
+
+/// A pattern binding all `fields` by value to the places used by
+/// `map_fields` with `Place::Param`.
+pub(crate) fn destructure_params(fields: &Fields) -> TokenStream {
+    match fields {
+        Fields::Named(FieldsNamed { named, .. }) => {
+            let params = named.iter().map(|field| &field.ident);
+            quote! { {#(#params),*} }
+        },
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
+            let params = unnamed.iter().enumerate().map(|(idx, field)| field_param(idx, field));
+            quote! { (#(#params),*) }
+        },
+        Fields::Unit => quote! {}
+    }
+}
+
+fn destructure_fields_with(prefix: &str, fields: &Fields, by_ref: bool) -> TokenStream {
+    let by_ref = if by_ref { Some(quote! { ref }) } else { None };
+    match fields {
+        Fields::Named(FieldsNamed { named, .. }) => {
+            let bindings: Punctuated<TokenStream, Comma> = named.iter()
+                .enumerate()
+                .map(|(idx, field)| {
+                    let ident = &field.ident;
+                    let binding = field_binding(prefix, idx, field);
+                    quote! { #ident: #by_ref #binding }
+                })
+                .collect();
+            quote! { {#bindings} }
+        },
+        Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
+            let bindings: Punctuated<TokenStream, Comma> = unnamed.iter()
+                .enumerate()
+                .map(|(idx, field)| {
+                    let binding = field_binding(prefix, idx, field);
+                    quote! { #by_ref #binding }
+                })
+                .collect();
+            quote! { (#bindings) }
+        },
+        Fields::Unit => quote! {}
+    }
+}
+
+/// Build the fields of a node from its existing `fields`, accessed as per
+/// `place`, by applying `f` to each of them.
+pub(crate) fn map_fields<F>(fields: &Fields, place: Place, f: F) -> TokenStream
 where
     F: Fn(&TokenStream, &Field) -> TokenStream
+{
+    build_fields(fields, |idx, field| f(&field_place(place, idx, field), field))
+}
+
+/// Build the fields of a node from the same `fields` of two nodes, accessed
+/// as per `place` and `other_place`, by applying `f` to each pair of them.
+pub(crate) fn zip_fields<F>(fields: &Fields, place: Place, other_place: Place, f: F) -> TokenStream
+where
+    F: Fn(&TokenStream, &TokenStream, &Field) -> TokenStream
+{
+    build_fields(fields, |idx, field| {
+        f(&field_place(place, idx, field), &field_place(other_place, idx, field), field)
+    })
+}
+
+fn build_fields<F>(fields: &Fields, f: F) -> TokenStream
+where
+    F: Fn(usize, &Field) -> TokenStream
 {
     match fields {
         Fields::Named(FieldsNamed { named, .. }) => {
             let mapped: Punctuated<TokenStream, Comma> = named.iter()
-                .map(|field| {
-                    let ident = {
-                        let ident = &field.ident;
-                        quote! { #ident }
-                    };
-                    let out = f(&ident, field);
+                .enumerate()
+                .map(|(idx, field)| {
+                    let ident = &field.ident;
+                    let out = f(idx, field);
                     quote! { #ident: #out }
                 })
                 .collect();
@@ -104,15 +270,64 @@ where
         Fields::Unnamed(FieldsUnnamed { unnamed, .. }) => {
             let mapped: Punctuated<TokenStream, Comma> = unnamed.iter()
                 .enumerate()
-                .map(|(idx, field)| {
-                    let field_index: Index = idx.into();
-                    let ident = quote! { #field_index };
-                    f(&ident, field)
-                })
-
+                .map(|(idx, field)| f(idx, field))
                 .collect();
             quote! { (#mapped) }
         },
         Fields::Unit => quote! {}
     }
 }
+
+/// Combine the errors of all `results` into one, so that they are all
+/// reported at once.
+pub(crate) fn collect_errors<I>(results: I) -> syn::Result<()>
+where
+    I: IntoIterator<Item = syn::Result<()>>
+{
+    let mut errors: Option<Error> = None;
+    for err in results.into_iter().filter_map(|result| result.err()) {
+        match &mut errors {
+            Some(errors) => errors.combine(err),
+            None => errors = Some(err)
+        }
+    }
+    match errors {
+        Some(errors) => Err(errors),
+        None => Ok(())
+    }
+}
+
+/// The snake case version of a camel case `ident`, e.g. `is_null` for
+/// `IsNull`, to be used as part of a larger identifier.
+pub(crate) fn snake_name(ident: &Ident) -> String {
+    let chars: Vec<char> = ident.unraw().to_string().chars().collect();
+    let mut out = String::new();
+    for (idx, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && idx > 0 {
+            let prev = chars[idx - 1];
+            let next_is_lower = chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+            if prev != '_' && (!prev.is_uppercase() || next_is_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+/// The snake case identifier of a camel case `ident`, e.g. `is_null` for
+/// `IsNull`. Keywords are made into raw identifiers, except `crate`, `self`
+/// and `super`, which cannot be raw and get a trailing underscore instead.
+pub(crate) fn snake_case(ident: &Ident) -> Ident {
+    let snake = snake_name(ident);
+    match snake.as_str() {
+        "crate" | "self" | "super" => format_ident!("{}_", snake, span = ident.span()),
+        _ => match syn::parse_str::<Ident>(&snake) {
+            Ok(mut snake) => {
+                snake.set_span(ident.span());
+                snake
+            },
+            Err(_) => Ident::new_raw(&snake, ident.span())
+        }
+    }
+}
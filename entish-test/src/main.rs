@@ -1,12 +1,17 @@
-#![feature(box_patterns, try_trait, proc_macro_hygiene)]
-
-use std::ops::{Add, Mul};
-
 #[macro_use] extern crate entish;
 use entish::prelude::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use entish::Located;
+use entish::arena::Arena;
+use entish::intern::Interner;
+use entish::path::{Step, TreePath};
+use entish::rewrite::{Order, RuleSet, Strategy};
 
 entish! {
-    #[derive(Map, MapOwned, From, IntoResult)]
+    #[derive(Map, MapOwned, MapMut, Traverse, Zip, From, IntoResult, IntoValidated)]
     #[entish(variants_as_structs)]
     enum Arithmetic {
         Plus {
@@ -21,6 +26,247 @@ entish! {
     }
 }
 
+entish! {
+    #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[derive(Map, MapOwned, MapMut, Traverse, Zip, IntoResult, IntoOption, IntoValidated)]
+    #[entish(tree = "Predicate", derive_tree(PartialEq, Eq, Hash, PartialOrd, Ord))]
+    enum Logic {
+        And {
+            left: Self,
+            right: Self
+        },
+        Not(Self),
+        Any(Vec<Self>),
+        Literal(bool)
+    }
+}
+
+/// A label that cannot be cloned
+#[derive(Debug, PartialEq)]
+pub struct Name(String);
+
+entish! {
+    #[derive(Debug, PartialEq, MapOwned, MapMut, Traverse)]
+    enum Labelled<T> {
+        Leaf(T),
+        Branch {
+            name: Name,
+            children: Vec<Self>
+        }
+    }
+}
+
+entish! {
+    #[derive(Debug, PartialEq)]
+    #[derive(Map, MapOwned, MapMut, Traverse, Zip, IntoResult, IntoOption, IntoValidated)]
+    #[entish(tree = "Shape")]
+    enum Layout {
+        Boxed(Box<Self>),
+        Pair([Self; 2]),
+        Split(Self, (Self, String, Self)),
+        Queue(VecDeque<Self>),
+        Named(BTreeMap<String, Self>),
+        Hashed(HashMap<u32, Self>),
+        Fallible(Result<Self, String>),
+        Sparse(Vec<Option<Self>>),
+        Leaf(u32)
+    }
+}
+
+entish! {
+    #[derive(Debug, PartialEq)]
+    #[derive(Map, MapOwned, MapMut, Traverse, Zip, IntoResult, IntoOption, IntoValidated)]
+    #[entish(variants_as_structs)]
+    enum Grid {
+        Row {
+            cells: [Option<Self>; 3],
+            meta: (Self, u8)
+        },
+        Cell(u8)
+    }
+}
+
+entish! {
+    #[derive(Debug, PartialEq, Map, MapOwned, MapMut, Traverse, Zip, IntoResult, IntoOption, IntoValidated)]
+    #[entish(tree = "Rose")]
+    struct Block {
+        stmts: Vec<Self>,
+        label: String
+    }
+}
+
+entish! {
+    #[derive(Debug, PartialEq, Map, MapOwned, MapMut, Traverse, Zip)]
+    #[entish(tree = "Formula")]
+    enum Term {
+        Lit(i64),
+        Add(Self, Self),
+        Block(Vec<Stmt>, Self)
+    }
+
+    #[derive(Debug, PartialEq, Map, MapOwned, MapMut, Traverse, Zip, From)]
+    #[entish(tree = "Script", variants_as_structs)]
+    enum Stmt {
+        Assign {
+            name: String,
+            value: Term
+        },
+        Seq(Vec<Self>)
+    }
+
+    #[derive(Debug, PartialEq, Map, MapOwned, MapMut, Traverse, Zip)]
+    #[entish(tree = "Request")]
+    struct Query {
+        setup: Option<Stmt>,
+        result: Term,
+        unions: Vec<Self>
+    }
+}
+
+// a node called `Tree` does not clash with the generated code
+entish! {
+    #[derive(Debug, PartialEq, Map, MapOwned)]
+    #[entish(tree = "Forest")]
+    enum Tree {
+        Node(Vec<Self>),
+        Leaf(u8)
+    }
+}
+
+// variants whose snake case is a keyword that cannot be raw
+entish! {
+    #[derive(Debug, PartialEq, Map, MapOwned, From)]
+    #[entish(tree = "Route", variants_as_structs)]
+    enum Segment {
+        Crate,
+        Super(u32),
+        Join(Self, Self)
+    }
+}
+
+/// Counts the leaves
+#[derive(Default)]
+struct Leaves(usize);
+
+impl<'t> TreeVisit<'t, Forest> for Leaves {
+    fn visit_leaf(&mut self, _: &'t u8) {
+        self.0 += 1
+    }
+}
+
+/// Rebuilds a forest as is
+struct Replant;
+
+impl TreeFold<Forest> for Replant {}
+
+/// Counts the `crate` segments of a route
+#[derive(Default)]
+struct Crates(usize);
+
+impl<'t> SegmentVisit<'t, Route> for Crates {
+    fn visit_crate(&mut self) {
+        self.0 += 1
+    }
+}
+
+/// Concatenates the names assigned by a script
+#[derive(Default)]
+struct Assigned(String);
+
+impl<'t> StmtVisit<'t, Formula, Script> for Assigned {
+    fn visit_assign(&mut self, name: &'t String, _: &'t Box<Formula>) {
+        self.0 += name
+    }
+}
+
+/// Sums the leaves of a shape, through all its containers
+#[derive(Default)]
+struct LeafSum(u32);
+
+impl<'t> LayoutVisit<'t, Shape> for LeafSum {
+    fn visit_leaf(&mut self, value: &'t u32) {
+        self.0 += value
+    }
+}
+
+/// Borrows the labels of the blocks, children first
+#[derive(Default)]
+struct Labels<'t>(Vec<&'t String>);
+
+impl<'t> BlockVisit<'t, Rose> for Labels<'t> {
+    fn visit_block(&mut self, stmts: &'t Vec<Box<Rose>>, label: &'t String) {
+        stmts.iter().for_each(|stmt| self.visit(stmt));
+        self.0.push(label)
+    }
+}
+
+/// Counts the blocks without statements
+#[derive(Default)]
+struct EmptyBlocks(usize);
+
+impl<'t> BlockVisit<'t, Rose> for EmptyBlocks
+{
+    fn visit_block(&mut self, stmts: &'t Vec<Box<Rose>>, label: &'t String) {
+        if stmts.is_empty() {
+            self.0 += 1
+        }
+        stmts.iter().for_each(|stmt| self.visit(stmt));
+        let _ = label;
+    }
+}
+
+fn layout_sum(node: Layout<u32>) -> u32 {
+    match node {
+        Layout::Boxed(inner) => *inner,
+        Layout::Pair([left, right]) => left + right,
+        Layout::Split(first, (second, _, third)) => first + second + third,
+        Layout::Queue(values) => values.into_iter().sum(),
+        Layout::Named(values) => values.values().sum(),
+        Layout::Hashed(values) => values.values().sum(),
+        Layout::Fallible(value) => value.unwrap_or(0),
+        Layout::Sparse(values) => values.into_iter().flatten().sum(),
+        Layout::Leaf(value) => value
+    }
+}
+
+/// Rebuilds a tree as is
+struct Identity;
+
+impl LayoutFold<Shape> for Identity {}
+
+impl Predicate
+{
+    fn evaluate(self) -> bool {
+        self.fold(&mut |node| match node {
+            Logic::And { left, right } => left && right,
+            Logic::Not(value) => !value,
+            Logic::Any(values) => values.into_iter().any(|value| value),
+            Logic::Literal(value) => value
+        })
+    }
+
+    /// Remove double negations, outermost first.
+    fn simplify(self) -> Self {
+        self.transform_down(&mut |predicate: Predicate| match predicate.into_inner() {
+            Logic::Not(inner) => match inner.into_inner() {
+                Logic::Not(value) => value,
+                inner => Predicate::not(Predicate::from(inner))
+            },
+            node => Predicate::from(node)
+        })
+    }
+
+    /// Replace literals by their value in `env`, failing on unknown ones.
+    fn substitute(self, env: &[bool]) -> Result<Self, String> {
+        self.try_transform_up(&mut |predicate: Predicate| match predicate.0 {
+            Logic::Literal(value) if !env.contains(&value) => {
+                Err(format!("no value for {}", value))
+            },
+            _ => Ok(predicate)
+        })
+    }
+}
+
 pub struct Expr(Arithmetic<Box<Self>>);
 
 impl ArithmeticTree for Expr
@@ -28,20 +274,186 @@ impl ArithmeticTree for Expr
     fn as_ref(&self) -> Arithmetic<&Self> {
         self.0.map(&mut |c| c.as_ref())
     }
+
+    fn into_inner(self) -> Arithmetic<Self> {
+        self.0.map_owned(&mut |c| *c)
+    }
+}
+
+impl MapMut<Expr> for Expr
+{
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut Expr)
+    {
+        self.0.map_mut(&mut |c: &'a mut Box<Expr>| f(c))
+    }
+}
+
+impl From<Arithmetic<Expr>> for Expr
+{
+    fn from(node: Arithmetic<Expr>) -> Self {
+        Expr(node.map_owned(&mut Box::new))
+    }
 }
 
 fn do_arithmetic(node: &Arithmetic<i32>) -> i32 {
-    match *op {
+    match *node {
         Arithmetic::Plus(Plus { left, right }) => left + right,
         Arithmetic::Times(Times { left, right }) => left * right,
         Arithmetic::Just(Just(v)) => v
     }
 }
 
+fn checked_arithmetic(node: Arithmetic<i32>) -> Result<i32, String> {
+    match node {
+        Arithmetic::Plus(Plus { left, right }) => {
+            left.checked_add(right).ok_or_else(|| "overflow".to_string())
+        },
+        Arithmetic::Times(Times { left, right }) => {
+            left.checked_mul(right).ok_or_else(|| "overflow".to_string())
+        },
+        Arithmetic::Just(Just(v)) => Ok(v)
+    }
+}
+
 impl Expr
 {
     fn compute_value(self) -> i32 {
-        self.fold(&mut |op| do_arithmetic)
+        self.fold(&mut |node| do_arithmetic(&node))
+    }
+
+    fn checked_value(self) -> Result<i32, String> {
+        self.try_fold(&mut checked_arithmetic)
+    }
+
+    fn overflows(self) -> Result<i32, Vec<Located<String>>> {
+        self.try_fold_all(&mut checked_arithmetic)
+    }
+
+    /// Replace additions of two literals by their sum, in place.
+    fn fold_additions(&mut self) {
+        self.transform_up_mut(&mut |expr: &mut Expr| {
+            let sum = match expr.0 {
+                Arithmetic::Plus(Plus { ref left, ref right }) => match (&left.0, &right.0) {
+                    (Arithmetic::Just(Just(left)), Arithmetic::Just(Just(right))) => left + right,
+                    _ => return
+                },
+                _ => return
+            };
+            *expr = Expr(Arithmetic::Just(Just(sum)));
+        })
+    }
+
+    /// Remove additions of `0` and multiplications by `1`, innermost first.
+    fn simplify(self) -> Self {
+        self.transform_up(&mut |expr: Expr| {
+            let is = |expr: &Expr, n: i32| matches!(expr.0, Arithmetic::Just(Just(v)) if v == n);
+            match expr.into_inner() {
+                Arithmetic::Plus(Plus { left, right }) if is(&left, 0) => right,
+                Arithmetic::Times(Times { left, right }) if is(&left, 1) => right,
+                Arithmetic::Plus(Plus { left, right }) if is(&right, 0) => left,
+                Arithmetic::Times(Times { left, right }) if is(&right, 1) => left,
+                node => Expr::from(node)
+            }
+        })
+    }
+}
+
+fn fold_plus(expr: &Expr) -> Option<Expr> {
+    expr_match! {
+        match expr {
+            Arithmetic::Plus(Plus {
+                left: Arithmetic::Just(Just(left)),
+                right: Arithmetic::Just(Just(right))
+            }) => Some(Expr(Arithmetic::Just(Just(left + right)))),
+            _ => None
+        }
+    }
+}
+
+fn fold_times(expr: &Expr) -> Option<Expr> {
+    expr_match! {
+        match expr {
+            Arithmetic::Times(Times {
+                left: Arithmetic::Just(Just(left)),
+                right: Arithmetic::Just(Just(right))
+            }) => Some(Expr(Arithmetic::Just(Just(left * right)))),
+            _ => None
+        }
+    }
+}
+
+/// The product of two literals, looking through additions of `0` on the left.
+fn literal_product(expr: &Expr) -> Option<i32> {
+    expr_match! {
+        match expr {
+            Arithmetic::Times(Times {
+                left: Arithmetic::Just(Just(left)),
+                right: Arithmetic::Just(Just(right))
+            }) => Some(left * right),
+            Arithmetic::Plus(Plus { left: Arithmetic::Just(Just(0)), right }) => {
+                literal_product(right)
+            },
+            _ => None
+        }
+    }
+}
+
+/// The literal on the left of a sum, possibly on the left of a product,
+/// if it is positive. Counts the times the guard is evaluated in
+/// `evaluations`.
+fn positive_left_literal(expr: &Expr, evaluations: &mut usize) -> Option<i32> {
+    expr_match! {
+        #[entish(max_depth = 2)]
+        match expr {
+            Arithmetic::Plus(Plus {
+                left: Arithmetic::Just(Just(value)) | Arithmetic::Times(Times { left: Arithmetic::Just(Just(value)), .. }),
+                ..
+            }) if { *evaluations += 1; value > 0 } => Some(value),
+            _ => None
+        }
+    }
+}
+
+/// Counts the literals of a predicate by value
+#[derive(Default)]
+struct LiteralCounter {
+    trues: usize,
+    falses: usize
+}
+
+impl<'t> LogicVisit<'t, Predicate> for LiteralCounter
+{
+    fn visit_literal(&mut self, field_0: &'t bool) {
+        if *field_0 {
+            self.trues += 1
+        } else {
+            self.falses += 1
+        }
+    }
+}
+
+/// Doubles every literal of an expression
+struct Doubler;
+
+impl ArithmeticFold<Expr> for Doubler
+{
+    fn fold_just(&mut self, field_0: i32) -> Expr {
+        Expr(Arithmetic::Just(Just(field_0 * 2)))
+    }
+}
+
+/// Removes negations of negations, innermost first
+struct DoubleNegation;
+
+impl LogicFold<Predicate> for DoubleNegation
+{
+    fn fold_not(&mut self, field_0: Predicate) -> Predicate {
+        match self.fold(field_0).into_inner() {
+            Logic::Not(inner) => inner,
+            inner => Predicate::not(Predicate::from(inner))
+        }
     }
 }
 
@@ -89,5 +501,604 @@ fn main() {
         )
     );
 
-    assert_eq!(13, an_expr.compute_value())
+    assert_eq!(None, literal_product(&an_expr));
+    let right: &Expr = match an_expr.0 {
+        Arithmetic::Plus(Plus { ref right, .. }) => right,
+        _ => unreachable!()
+    };
+    assert_eq!(Some(12), literal_product(right));
+
+    // the guard of an arm is evaluated once for the alternative that matches
+    let just = |value| Box::new(Expr(Arithmetic::Just(Just(value))));
+    let plus = |left, right| Expr(Arithmetic::Plus(Plus { left, right }));
+    let times = |left, right| Box::new(Expr(Arithmetic::Times(Times { left, right })));
+    let mut evaluations = 0;
+    assert_eq!(Some(3), positive_left_literal(&plus(times(just(3), just(4)), just(1)), &mut evaluations));
+    assert_eq!(1, evaluations);
+    assert_eq!(Some(2), positive_left_literal(&plus(just(2), just(1)), &mut evaluations));
+    assert_eq!(None, positive_left_literal(&plus(times(just(-3), just(4)), just(1)), &mut evaluations));
+    assert_eq!(3, evaluations);
+
+    // the values of the children of a node, if they are all literals
+    let mut just_value = |c: &Expr| match c.0 {
+        Arithmetic::Just(Just(v)) => Some(v),
+        _ => None
+    };
+    assert!(an_expr.as_ref().traverse(&mut just_value).is_none());
+    let right_values = right.as_ref().traverse(&mut just_value);
+    assert!(matches!(right_values, Some(Arithmetic::Times(Times { left: 2, right: 6 }))));
+
+    // pair up the children of nodes of the same shape
+    let plus: Arithmetic<i32> = Arithmetic::Plus(Plus { left: 1, right: 2 });
+    let costs: Arithmetic<&str> = Arithmetic::Plus(Plus { left: "cheap", right: "expensive" });
+    let annotated = plus.zip_with(&costs, &mut |v, cost| format!("{} ({})", v, cost));
+    assert!(matches!(annotated, Some(Arithmetic::Plus(Plus { ref left, ref right })) if left == "1 (cheap)" && right == "2 (expensive)"));
+    let times: Arithmetic<i32> = Arithmetic::Times(Times { left: 1, right: 2 });
+    assert!(plus.zip_with(&times, &mut |l, r| l + r).is_none());
+
+    // constant folding, top-down: `fold_plus` does not fire on the root as
+    // its right child is only folded afterwards
+    let rules = RuleSet::new()
+        .rule("fold_plus", fold_plus)
+        .rule("fold_times", fold_times);
+    let (an_expr, report) = an_expr.rewrite(&rules, Strategy::Once(Order::TopDown));
+    assert!(matches!(an_expr.0, Arithmetic::Plus(_)));
+    assert_eq!((0, 1, false), (report.count("fold_plus"), report.count("fold_times"), report.converged));
+
+    // then to a fixpoint
+    let (an_expr, report) = an_expr.rewrite(&rules, Strategy::Fixpoint(Order::BottomUp));
+    assert!(matches!(an_expr.0, Arithmetic::Just(Just(13))));
+    assert_eq!((1, 2, true), (report.total(), report.passes, report.converged));
+
+    assert_eq!(13, an_expr.compute_value());
+
+    // a left-deep chain of additions, too deep to be folded recursively
+    let depth = 1_000_000;
+    let just_one = || Box::new(Expr(Arithmetic::Just(Just(1))));
+    let mut deep_expr = Expr(Arithmetic::Just(Just(1)));
+    for _ in 0..depth {
+        deep_expr = Expr(Arithmetic::Plus(Plus { left: Box::new(deep_expr), right: just_one() }));
+    }
+    // folding additions in place, from leaves to root, goes all the way up
+    deep_expr.fold_additions();
+    assert!(matches!(deep_expr.0, Arithmetic::Just(Just(v)) if v == depth + 1));
+    assert_eq!(Ok(depth + 1), deep_expr.checked_value());
+
+    // 1 + (2 * 6) by path
+    let nested = Expr(Arithmetic::Plus(Plus {
+        left: just_one(),
+        right: Box::new(Expr(Arithmetic::Times(Times {
+            left: Box::new(Expr(Arithmetic::Just(Just(2)))),
+            right: Box::new(Expr(Arithmetic::Just(Just(6))))
+        })))
+    }));
+    assert_eq!(vec!["left", "right"], nested.0.child_fields());
+    let two = nested.get(&TreePath::root().field("right", 0).field("left", 0));
+    assert!(matches!(two.map(|e| &e.0), Some(Arithmetic::Just(Just(2)))));
+    // the paths of all nodes name the fields they go through, and lead back
+    // to their nodes
+    let paths: Vec<TreePath> = nested.iter_paths().map(|(path, _)| path).collect();
+    let right_left = TreePath::root().field("right", 0).field("left", 0);
+    assert_eq!(vec![TreePath::root(), TreePath::root().field("left", 0), TreePath::root().field("right", 0), right_left.clone()], paths[..4]);
+    assert!(nested.iter_paths().all(|(path, node)| nested.get(&path).is_some_and(|found| std::ptr::eq(found, node))));
+    assert!(std::ptr::eq(two.unwrap(), nested.iter_paths().find(|(path, _)| *path == right_left).unwrap().1));
+    let nested = Doubler.fold(nested);
+    let mut zipper = nested.zipper();
+    assert!(zipper.down(1) && zipper.down(1));
+    zipper.replace(Expr(Arithmetic::Just(Just(7))));
+    assert_eq!(2 + 4 * 7, zipper.into_tree().compute_value());
+
+    // the same chain stored in an arena, and back
+    let mut deep_expr = Expr(Arithmetic::Just(Just(1)));
+    for _ in 0..depth {
+        deep_expr = Expr(Arithmetic::Plus(Plus { left: Box::new(deep_expr), right: just_one() }));
+    }
+    let mut arena = Arena::new();
+    let root = deep_expr.fold(&mut |node| arena.push(node));
+    assert_eq!(arena.len() as i32, 2 * depth + 1);
+    assert_eq!(depth + 1, arena.tree(root).fold(&mut |node| do_arithmetic(&node)));
+    let root_ref = arena.tree(root);
+    let right_id = match root_ref.as_ref() {
+        Arithmetic::Plus(Plus { right, .. }) => right.id(),
+        _ => unreachable!()
+    };
+    assert!(matches!(arena.get(right_id), Arithmetic::Just(Just(1))));
+    let deep_expr = arena.tree(root).fold(&mut Expr::from);
+    assert_eq!(Ok(depth + 1), deep_expr.checked_value());
+
+    // (MAX + 1) + (2 * (MAX * 2)) overflows in two places
+    let just = |v| Box::new(Expr(Arithmetic::Just(Just(v))));
+    let plus = |left, right| Box::new(Expr(Arithmetic::Plus(Plus { left, right })));
+    let times = |left, right| Box::new(Expr(Arithmetic::Times(Times { left, right })));
+    let overflowing = plus(plus(just(i32::MAX), just(1)), times(just(2), times(just(i32::MAX), just(2))));
+    let overflow = |path| Located { path, error: "overflow".to_string() };
+    assert_eq!(Err(vec![overflow(vec![0]), overflow(vec![1, 1])]), overflowing.overflows());
+    assert_eq!(Ok(7), plus(just(3), just(4)).overflows());
+
+    // 1 * (0 + 7) simplifies to 7
+    let to_simplify = Expr(Arithmetic::Times(Times {
+        left: just(1),
+        right: Box::new(Expr(Arithmetic::Plus(Plus { left: just(0), right: just(7) })))
+    }));
+    assert!(matches!(to_simplify.simplify().0, Arithmetic::Just(Just(7))));
+
+    // a_predicate = !(true && false) && any(false, true)
+    let a_predicate = Predicate::and(
+        Predicate::not(
+            Predicate::and(Predicate::literal(true), Predicate::literal(false))
+        ),
+        Predicate::any(vec![Predicate::literal(false), Predicate::literal(true)])
+    );
+
+    let literals: Option<Logic<bool>> = a_predicate
+        .as_ref()
+        .map(&mut |c| match c.0 {
+            Logic::Literal(value) => Some(value),
+            _ => None
+        })
+        .into_option();
+    assert!(literals.is_none());
+
+    let literals = a_predicate.as_ref().traverse(&mut |c: &Predicate| match c.0 {
+        Logic::Not(_) => Ok(false),
+        Logic::Any(_) => Ok(true),
+        _ => Err(())
+    });
+    assert!(matches!(literals, Ok(Logic::And { left: false, right: true })));
+
+    assert!(a_predicate.evaluate());
+
+    // !!(!!true && false) simplifies to true && false
+    let double_not = |p| Predicate::not(Predicate::not(p));
+    let simplified = double_not(
+        Predicate::and(double_not(Predicate::literal(true)), Predicate::literal(false))
+    ).simplify();
+    assert!(matches!(*simplified, Logic::And { ref left, .. } if matches!(left.0, Logic::Literal(true))));
+    assert!(!simplified.substitute(&[true, false]).unwrap().evaluate());
+
+    // containers must have the same length
+    let any: Logic<i32> = Logic::Any(vec![1, 2]);
+    assert!(matches!(any.zip_with(&Logic::Any(vec![3, 4]), &mut |l, r| l * r), Some(Logic::Any(ref v)) if v == &[3, 8]));
+    assert!(any.zip_with(&Logic::Any(vec![3]), &mut |l, r| l * r).is_none());
+    assert!(any.zip_with(&Logic::Literal(true), &mut |l, r| l * r).is_none());
+
+    // labels are moved, not cloned
+    let branch = Labelled::Branch { name: Name("root".to_string()), children: vec![1, 2] };
+    let doubled: Labelled<Name, i32> = branch.map_owned(&mut |c| c * 2);
+    assert!(matches!(doubled, Labelled::Branch { ref name, ref children } if name.0 == "root" && children == &[2, 4]));
+    let leaf: Labelled<Name, i32> = Labelled::Leaf(Name("leaf".to_string()));
+    assert_eq!(Some(Labelled::Leaf(Name("leaf".to_string()))), leaf.traverse(&mut |c: i32| Some(c)));
+
+    // structural comparisons
+    let hash = |predicate: &Predicate| {
+        let mut hasher = DefaultHasher::new();
+        predicate.hash(&mut hasher);
+        hasher.finish()
+    };
+    let not_any = |values: Vec<bool>| {
+        Predicate::not(Predicate::any(values.into_iter().map(Predicate::literal).collect()))
+    };
+    assert_eq!(not_any(vec![true, false]), not_any(vec![true, false]));
+    assert_eq!(hash(&not_any(vec![true, false])), hash(&not_any(vec![true, false])));
+    assert_ne!(not_any(vec![true, false]), not_any(vec![true]));
+    assert!(not_any(vec![false, true]) < not_any(vec![true, false]));
+    assert!(not_any(vec![true]) < not_any(vec![true, false]));
+    assert!(not_any(vec![]) < Predicate::not(Predicate::literal(true)));
+
+    let deep_not = |depth| {
+        let mut predicate = Predicate::literal(true);
+        for _ in 0..depth {
+            predicate = Predicate::not(predicate);
+        }
+        predicate
+    };
+    let (deep, twin, deeper) = (deep_not(depth), deep_not(depth), deep_not(depth + 1));
+    assert!(deep.tree_eq(&twin) && deep == twin);
+    assert_eq!(hash(&deep), hash(&twin));
+    assert_eq!(depth as usize, deep.iter_postorder_with_depth().map(|(depth, _)| depth).max().unwrap());
+    assert!(deeper < deep && deep != deeper);
+    // take the trees apart without recursing
+    for predicate in [deep, twin, deeper] {
+        predicate.fold(&mut |_| ());
+    }
+
+    // walk every node
+    let plan = Predicate::any(vec![
+        Predicate::and(Predicate::literal(true), Predicate::not(Predicate::literal(false))),
+        Predicate::literal(false)
+    ]);
+    let literals = plan.iter_preorder().filter(|p| matches!(p.0, Logic::Literal(_))).count();
+    assert_eq!(3, literals);
+    let depths = |iter: &mut dyn Iterator<Item = (usize, &Predicate)>| {
+        iter.map(|(depth, _)| depth).collect::<Vec<_>>()
+    };
+    assert_eq!(vec![0, 1, 2, 2, 3, 1], depths(&mut plan.iter_preorder_with_depth()));
+    assert_eq!(vec![2, 3, 2, 1, 1, 0], depths(&mut plan.iter_postorder_with_depth()));
+    assert_eq!(vec![0, 1, 1, 2, 2, 3], depths(&mut plan.iter_bfs_with_depth()));
+    assert!(matches!(plan.iter_postorder().last().map(|p| &p.0), Some(Logic::Any(_))));
+    assert!(matches!(plan.iter_bfs().nth(2).map(|p| &p.0), Some(Logic::Literal(false))));
+
+    // address nodes by path
+    let not_false = TreePath::root().child(0).field("right", 0);
+    assert!(matches!(plan.get(&not_false).map(|p| &p.0), Some(Logic::Not(_))));
+    assert!(matches!(plan.get(&TreePath::root().field("0", 1)).map(|p| &p.0), Some(Logic::Literal(false))));
+    assert!(plan.get(&TreePath::root().field("left", 0)).is_none());
+    assert_eq!(vec!["0", "0"], plan.0.child_fields());
+    let (path, _) = plan.iter_paths().find(|(_, p)| matches!(p.0, Logic::Not(_))).unwrap();
+    // unnamed fields are gone down by index, and named ones by name
+    assert_eq!(not_false, path);
+    // paths share their prefixes as they go up and down the tree, and still
+    // lead to their nodes
+    assert!(plan.iter_paths().zip(plan.iter_preorder_with_depth()).all(|((path, node), (depth, other))| {
+        path.len() == depth && std::ptr::eq(node, other) && plan.get(&path).is_some_and(|found| std::ptr::eq(found, node))
+    }));
+    let mut plan = plan;
+    let replaced = plan.replace_at(&not_false.clone().child(0), Predicate::literal(true));
+    assert!(matches!(replaced.map(|p| p.0), Ok(Logic::Literal(false))));
+    assert!(plan.replace_at(&not_false.child(1), Predicate::literal(true)).is_err());
+    assert!(!Predicate::and(plan, Predicate::literal(true)).evaluate());
+
+    // children by name
+    let names = |node: &Logic<char>| -> Vec<String> {
+        node.children_named().iter().map(|(name, _)| name.to_string()).collect()
+    };
+    let and = Logic::And { left: 'l', right: 'r' };
+    assert_eq!(vec!["left", "right"], names(&and));
+    assert_eq!(Some(&'r'), and.child_by_name("right"));
+    let any = Logic::Any(vec!['a', 'b']);
+    assert_eq!(vec!["0[0]", "0[1]"], names(&any));
+    assert_eq!((Some(&'b'), None), (any.child_by_name("0[1]"), any.child_by_name("0")));
+    let branch: Labelled<(), _> = Labelled::Branch { name: Name("root".to_string()), children: vec![1, 2, 3] };
+    assert_eq!(Some(&3), branch.child_by_name("children[2]"));
+    let plus = Plus { left: 1, right: 2 };
+    assert_eq!((Some(&1), None), (plus.child_by_name("left"), plus.child_by_name("middle")));
+    let plus = Arithmetic::Plus(plus);
+    let (name, child) = plus.children_named()[1];
+    assert_eq!((Step::Field { name: "right", index: 0 }, &2), (Step::from(name), child));
+    assert!(Arithmetic::<i32>::Just(Just(3)).children_named().is_empty());
+
+    // kinds of nodes
+    assert_eq!([LogicKind::And, LogicKind::Not, LogicKind::Any, LogicKind::Literal], LogicKind::ALL);
+    let arities: Vec<_> = LogicKind::ALL.iter().map(|kind| (kind.name(), kind.arity())).collect();
+    assert_eq!(vec![("And", Some(2)), ("Not", Some(1)), ("Any", None), ("Literal", Some(0))], arities);
+    assert_eq!((&["0"][..], &[][..]), (LogicKind::Literal.fields(), LogicKind::Literal.child_fields()));
+    assert_eq!(&["left", "right"], ArithmeticKind::Plus.child_fields());
+    assert_eq!(LogicKind::Not, Predicate::not(Predicate::literal(true)).kind());
+    assert_eq!(ArithmeticKind::Just, Arithmetic::<()>::Just(Just(1)).kind());
+    let mut by_kind = std::collections::HashMap::new();
+    let tree = Predicate::and(Predicate::literal(true), Predicate::not(Predicate::literal(false)));
+    for node in tree.iter_preorder() {
+        *by_kind.entry(node.kind()).or_insert(0) += 1;
+    }
+    assert_eq!((Some(&2), None), (by_kind.get(&LogicKind::Literal), by_kind.get(&LogicKind::Any)));
+
+    // visit and fold by variant
+    let mut counter = LiteralCounter::default();
+    let tree = Predicate::any(vec![
+        Predicate::literal(true),
+        Predicate::and(Predicate::literal(false), Predicate::not(Predicate::not(Predicate::literal(true))))
+    ]);
+    counter.visit(&tree);
+    assert_eq!((2, 1), (counter.trues, counter.falses));
+    let expected = Predicate::any(vec![
+        Predicate::literal(true),
+        Predicate::and(Predicate::literal(false), Predicate::literal(true))
+    ]);
+    assert_eq!(expected, DoubleNegation.fold(tree));
+
+    // move around and edit with a zipper
+    let mut zipper = Predicate::any(vec![
+        Predicate::literal(false),
+        Predicate::not(Predicate::literal(true)),
+        Predicate::literal(false)
+    ]).zipper();
+    assert!(zipper.down(1) && zipper.down(0));
+    assert_eq!(TreePath::from(vec![1, 0]), zipper.path());
+    zipper.replace(Predicate::literal(false));
+    assert!(zipper.up() && zipper.right());
+    assert_eq!((Some(2), 1), (zipper.index(), zipper.depth()));
+    assert!(!zipper.right() && !zipper.down(0));
+    assert!(zipper.left() && zipper.left() && !zipper.left());
+    assert_eq!(&Predicate::literal(false), zipper.focus());
+    *zipper.focus_mut() = Predicate::not(Predicate::literal(true));
+    assert!(zipper.up() && zipper.is_root() && !zipper.up());
+    let edited = zipper.into_tree();
+    assert_eq!(
+        Predicate::any(vec![
+            Predicate::not(Predicate::literal(true)),
+            Predicate::not(Predicate::literal(false)),
+            Predicate::literal(false)
+        ]),
+        edited
+    );
+    assert!(edited.evaluate());
+
+    // share identical subtrees
+    let mut interner = Interner::new();
+    let shared = || Predicate::and(Predicate::literal(true), Predicate::not(Predicate::literal(false)));
+    let left = shared().fold(&mut |node| interner.intern(node));
+    let right = shared().fold(&mut |node| interner.intern(node));
+    let root = interner.intern(Logic::Any(vec![left, right, left]));
+    assert_eq!(left, right);
+    assert_eq!(interner.len(), 5);
+    let literal = interner.lookup(&Logic::Literal(false)).map(|id| interner.tree(id).node());
+    assert_eq!(literal, Some(&Logic::Literal(false)));
+    let tree = interner.tree(root);
+    match tree.as_ref() {
+        Logic::Any(children) => assert!(children.len() == 3 && children[0] == children[1]),
+        _ => unreachable!()
+    }
+    assert!(tree.tree_eq(&interner.tree(root)));
+    assert!(tree.fold(&mut |node: Logic<bool>| match node {
+        Logic::And { left, right } => left && right,
+        Logic::Not(value) => !value,
+        Logic::Any(values) => values.into_iter().any(|value| value),
+        Logic::Literal(value) => value
+    }));
+
+    // negate all literals in place
+    let mut negated = Predicate::and(
+        Predicate::literal(true),
+        Predicate::any(vec![Predicate::literal(false), Predicate::literal(false)])
+    );
+    negated.for_each_mut(&mut |predicate: &mut Predicate| {
+        if let Logic::Literal(ref mut value) = predicate.0 {
+            *value = !*value
+        }
+    });
+    assert!(!negated.evaluate());
+
+    let falsy = Predicate::any(vec![
+        Predicate::literal(true),
+        Predicate::literal(false),
+        Predicate::not(Predicate::literal(false))
+    ]);
+    let falsy = falsy.try_fold_all(&mut |node: Logic<()>| match node {
+        Logic::Literal(false) => Err("false"),
+        _ => Ok(())
+    });
+    let located = |path| Located { path, error: "false" };
+    assert_eq!(Err(vec![located(vec![1]), located(vec![2, 0])]), falsy);
+
+    let unknown = Predicate::not(Predicate::literal(false)).substitute(&[true]);
+    assert_eq!(Err("no value for false".to_string()), unknown.map(|_| ()));
+
+    // children in all kinds of containers
+    let shape = || Shape::sparse(vec![
+        Some(Shape::split(
+            Shape::boxed(Box::new(Shape::leaf(1))),
+            (Shape::pair([Shape::leaf(2), Shape::leaf(3)]), "label".to_string(), Shape::queue(vec![Shape::leaf(4)].into()))
+        )),
+        None,
+        Some(Shape::named(vec![("five".to_string(), Shape::leaf(5))].into_iter().collect())),
+        Some(Shape::hashed(vec![(6, Shape::leaf(6))].into_iter().collect())),
+        Some(Shape::fallible(Ok(Shape::leaf(7)))),
+        Some(Shape::fallible(Err("none".to_string())))
+    ]);
+    assert_eq!(16, shape().iter_preorder().count());
+    assert_eq!(shape(), Identity.fold(shape()));
+    assert_eq!(28, shape().fold(&mut layout_sum));
+    let mut sum = LeafSum::default();
+    sum.visit(&shape());
+    assert_eq!(28, sum.0);
+
+    let node = Layout::Split(1, (2, "label".to_string(), 3));
+    let names: Vec<String> = node.children_named().iter().map(|(name, _)| name.to_string()).collect();
+    assert_eq!(vec!["0", "1[0]", "1[1]"], names);
+    assert_eq!(Some(&3), node.child_by_name("1[1]"));
+    assert_eq!(Some(Layout::Split(2, (4, "label".to_string(), 6))), node.zip_with(&node, &mut |a, b| a + b));
+    // nodes with different labels do not have the same shape
+    let relabelled = Layout::Split(1, (2, "other".to_string(), 3));
+    assert_eq!(None, node.zip_with(&relabelled, &mut |a, b| a + b));
+    assert_eq!(None, Layout::Leaf(1).zip_with(&Layout::<u32>::Leaf(2), &mut |a: &u32, b: &u32| a + b));
+    let arities: Vec<Option<usize>> = LayoutKind::ALL.iter().map(|kind| kind.arity()).collect();
+    assert_eq!(vec![Some(1), Some(2), Some(3), None, None, None, None, None, Some(0)], arities);
+
+    let mut sparse = Layout::Sparse(vec![Some(1), None, Some(2)]);
+    sparse.map_mut(&mut |value| *value += 1);
+    assert_eq!(Layout::Sparse(vec![Some(2), None, Some(3)]), sparse);
+    assert_eq!(Some(Layout::Sparse(vec![Some(1), None, Some(2)])), sparse.traverse(&mut |value: u32| value.checked_sub(1)));
+    let options = Layout::Sparse(vec![Some(Some(1)), None]);
+    assert_eq!(Some(Layout::Sparse(vec![Some(1), None])), options.into_option());
+    let options = Layout::Sparse(vec![Some(Some(1)), Some(None)]);
+    assert_eq!(None, options.into_option());
+    let results = Layout::Sparse(vec![Some(Err("a")), None, Some(Ok(1)), Some(Err("b"))]);
+    assert_eq!(Err("a"), results.into_result());
+    let results = Layout::Sparse(vec![Some(Err(vec!["a"])), None, Some(Ok(1)), Some(Err(vec!["b"]))]);
+    assert_eq!(Err(vec!["a", "b"]), results.into_validated());
+
+    let named = |key: &str| Layout::Named(vec![(key.to_string(), 1)].into_iter().collect());
+    assert_eq!(Some(named("a").map(&mut |value| value * 2)), named("a").zip_with(&named("a"), &mut |a, b| a + b));
+    assert_eq!(None, named("a").zip_with(&named("b"), &mut |a, b| a + b));
+
+    // children of maps stay under their keys, and their paths lead to them
+    let named = || Shape::named((0..20).map(|key| (format!("{:02}", key), Shape::leaf(key))).collect());
+    let under_their_keys = named().fold(&mut |node: Layout<u32>| match node {
+        Layout::Named(values) => values.iter().filter(|(key, value)| key.parse() == Ok(**value)).count() as u32,
+        Layout::Leaf(value) => value,
+        _ => unreachable!()
+    });
+    assert_eq!(20, under_their_keys);
+    let named = named();
+    assert!(named.iter_paths().all(|(path, node)| named.get(&path).is_some_and(|found| std::ptr::eq(found, node))));
+    let leaves: Vec<&Shape> = named.iter_children().collect();
+    let names: Vec<String> = named.as_ref().children_named().iter().map(|(name, _)| name.to_string()).collect();
+    assert_eq!("0[7]", names[7]);
+    assert_eq!(Shape::leaf(7), *leaves[7]);
+
+    // children of hash maps are paired up by key, whatever the order of the maps
+    let hashed = |keys: &[u32]| Layout::Hashed(keys.iter().map(|key| (*key, *key)).collect());
+    let sums = (0..20).map(|key| (key, 2 * key)).collect();
+    assert_eq!(Some(Layout::Hashed(sums)), hashed(&(0..20).collect::<Vec<_>>()).zip_with(&hashed(&(0..20).rev().collect::<Vec<_>>()), &mut |a, b| a + b));
+    assert_eq!(None, hashed(&[0, 1]).zip_with(&hashed(&[0, 2]), &mut |a, b| a + b));
+
+    // and stay under their keys, in an order that paths and names agree on
+    let hashed = || Shape::hashed((0..20).map(|key| (key, Shape::leaf(key))).collect());
+    let under_their_keys = hashed().fold(&mut |node: Layout<u32>| match node {
+        Layout::Hashed(values) => values.iter().filter(|(key, value)| key == value).count() as u32,
+        Layout::Leaf(value) => value,
+        _ => unreachable!()
+    });
+    assert_eq!(20, under_their_keys);
+    let hashed = hashed();
+    assert!(hashed.iter_paths().all(|(path, node)| hashed.get(&path).is_some_and(|found| std::ptr::eq(found, node))));
+    let leaves: Vec<&Shape> = hashed.iter_children().collect();
+    let node = hashed.as_ref();
+    let named = node.children_named();
+    assert!(named.iter().zip(leaves).all(|((_, named), leaf)| std::ptr::eq(**named, leaf)));
+
+    let fallible = Layout::Fallible(Err("none".to_string()));
+    assert_eq!(None, fallible.zip_with(&Layout::Fallible(Ok(1)), &mut |a: &u32, b: &u32| a + b));
+    assert_eq!(None, fallible.zip_with(&Layout::Fallible(Err("other".to_string())), &mut |a: &u32, b: &u32| a + b));
+    assert_eq!(Some(Layout::Fallible(Err("none".to_string()))), fallible.zip_with(&fallible, &mut |a: &u32, b: &u32| a + b));
+
+    let row = Grid::Row(Row { cells: [Some(1), None, Some(2)], meta: (3, 0) });
+    assert_eq!(Grid::Row(Row { cells: [Some(10), None, Some(20)], meta: (30, 0) }), row.map(&mut |value| value * 10));
+    let names: Vec<String> = match &row {
+        Grid::Row(row) => row.children_named().iter().map(|(name, _)| name.to_string()).collect(),
+        Grid::Cell(_) => unreachable!()
+    };
+    assert_eq!(vec!["cells[0]", "cells[1]", "meta[0]"], names);
+    let zipped = row.zip_with(&Grid::Row(Row { cells: [Some(1), Some(2), Some(2)], meta: (3, 0) }), &mut |a, b| a + b);
+    assert_eq!(None, zipped);
+    let zipped = row.zip_with(&Grid::Row(Row { cells: [Some(1), None, Some(2)], meta: (3, 1) }), &mut |a, b| a + b);
+    assert_eq!(None, zipped);
+    let options = Grid::Row(Row { cells: [Some(Some(1)), None, Some(None)], meta: (Some(3), 0) });
+    assert_eq!(None, options.into_option());
+
+    // a tree declared by a struct
+    let leaf = |label: &str| Rose::block(Vec::new(), label.to_string());
+    let rose = || Rose::block(vec![leaf("a"), Rose::block(vec![leaf("b"), leaf("c")], "d".to_string())], "e".to_string());
+    let labels = rose().fold(&mut |Block { stmts, label }: Block<String>| stmts.concat() + &label);
+    assert_eq!("abcde", labels);
+    let block = Block { stmts: vec![1, 2], label: "sum".to_string() };
+    assert_eq!(Block { stmts: vec![2, 4], label: "sum".to_string() }, block.map(&mut |value| value * 2));
+    assert_eq!(Some(&2), block.child_by_name("stmts[1]"));
+    assert_eq!((BlockKind::Block, None), (block.kind(), BlockKind::Block.arity()));
+    let mut empty = EmptyBlocks::default();
+    empty.visit(&leaf("a"));
+    empty.visit(&Rose::block(vec![leaf("a"), leaf("b")], "c".to_string()));
+    assert_eq!(3, empty.0);
+    // visitors borrow the fields of the nodes rather than cloning them
+    let rose = rose();
+    let mut labels = Labels::default();
+    labels.visit(&rose);
+    assert_eq!(vec!["a", "b", "c", "d", "e"], labels.0);
+    assert!(std::ptr::eq(labels.0[4], &rose.label));
+
+    // a family of trees with children of each other's types
+    let node: Term<i32, u8> = Term::Block(vec![1, 2], 3);
+    assert_eq!(Term::Block(vec![10, 20], 3), node.map_owned_stmt(&mut |stmt| stmt * 10));
+    assert_eq!(Term::Block(vec![1, 2], 4), Term::Block(vec![1, 2], 3).map_owned(&mut |term: u8| term + 1));
+    let assign: Stmt<i32, u8> = Assign { name: "x".to_string(), value: 1 }.into();
+    assert_eq!(Stmt::from(Assign { name: "x".to_string(), value: 2 }), assign.map_owned_term(&mut |term| term + 1));
+    assert_eq!(Stmt::Seq(Seq(vec![1])), Stmt::<i32, u8>::Seq(Seq(vec![1])).map_owned_term(&mut |term| term + 1));
+    // children of each other type can also be borrowed, mutated and
+    // traversed on their own
+    let mut node: Term<i32, u8> = Term::Block(vec![1, 2], 3);
+    assert_eq!(Term::Block(vec!["1".to_string(), "2".to_string()], 3), node.map_stmt(&mut |stmt| stmt.to_string()));
+    node.map_mut_stmt(&mut |stmt| *stmt += 1);
+    assert_eq!(Term::Block(vec![2, 3], 3), node);
+    assert_eq!(Ok(Term::Block(vec![1, 2], 3)), node.try_traverse_stmt(&mut |stmt| match stmt {
+        0 => Err("zero"),
+        stmt => Ok(stmt - 1)
+    }));
+    assert_eq!(None, Term::<i32, u8>::Block(vec![1, 0], 3).traverse_stmt(&mut |stmt| 10i32.checked_div(stmt)));
+    let mut assign: Stmt<i32, u8> = Assign { name: "x".to_string(), value: 1 }.into();
+    assert_eq!(Stmt::from(Assign { name: "x".to_string(), value: &1 }), assign.map_term(&mut |term| term));
+    assert_eq!(Stmt::Seq(Seq(vec![1])), Stmt::<i32, u8>::Seq(Seq(vec![1])).map_term(&mut |term| term + 1));
+    assign.map_mut_term(&mut |term| *term *= 10);
+    assert_eq!(Some(Stmt::from(Assign { name: "x".to_string(), value: 11 })), assign.traverse_term(&mut |term| term.checked_add(1)));
+    // `Zip` only pairs up children of type `Self`, and compares the others
+    // like the other fields of nodes
+    let zipped = Term::<i32, u8>::Block(vec![1, 2], 3).zip_with(&Term::Block(vec![1, 2], 4), &mut |a, b| a + b);
+    assert_eq!(Some(Term::Block(vec![1, 2], 7)), zipped);
+    assert_eq!(None, Term::<i32, u8>::Block(vec![1, 2], 3).zip_with(&Term::Block(vec![1], 4), &mut |a, b| a + b));
+
+    let lit = |value| Formula::lit(value);
+    let assign = |name: &str, value| Script::assign(name.to_string(), value);
+    let block = Formula::block(
+        vec![assign("x", lit(1)), Script::seq(vec![assign("y", Formula::add(lit(2), lit(3)))])],
+        lit(4)
+    );
+    let request = Request::query(
+        Some(assign("z", block)),
+        lit(5),
+        vec![Request::query(None, Formula::add(lit(6), lit(7)), Vec::new())]
+    );
+    // the sum of the literals, and the names assigned
+    let (sum, names) = request.fold_family(
+        &mut |term: Term<(i64, String), (i64, String)>| match term {
+            Term::Lit(value) => (value, String::new()),
+            Term::Add((a, x), (b, y)) => (a + b, x + &y),
+            Term::Block(stmts, (value, name)) => stmts
+                .into_iter()
+                .fold((value, name), |(a, x), (b, y)| (a + b, x + &y))
+        },
+        &mut |stmt: Stmt<(i64, String), (i64, String)>| match stmt {
+            Stmt::Assign(Assign { name, value: (value, names) }) => (value, names + &name),
+            Stmt::Seq(Seq(stmts)) => stmts.into_iter().fold((0, String::new()), |(a, x), (b, y)| (a + b, x + &y))
+        },
+        &mut |query: Query<(i64, String), (i64, String), (i64, String)>| {
+            let (setup, setup_names) = query.setup.unwrap_or_default();
+            query.unions
+                .into_iter()
+                .fold((setup + query.result.0, setup_names + &query.result.1), |(a, x), (b, y)| (a + b, x + &y))
+        }
+    );
+    assert_eq!((28, "xyz".to_string()), (sum, names));
+
+    // a chain of blocks assigning each other, too deep to be folded
+    // recursively
+    let mut deep = lit(0);
+    for _ in 0..depth {
+        deep = Formula::block(vec![assign("x", deep)], lit(1));
+    }
+    let assigned = deep.fold_family(
+        &mut |term: Term<usize, usize>| match term {
+            Term::Lit(_) => 0,
+            Term::Add(a, b) => a + b,
+            Term::Block(stmts, value) => stmts.into_iter().sum::<usize>() + value
+        },
+        &mut |stmt: Stmt<usize, usize>| match stmt {
+            Stmt::Assign(Assign { value, .. }) => value + 1,
+            Stmt::Seq(Seq(stmts)) => stmts.into_iter().sum()
+        },
+        &mut |query: Query<usize, usize, usize>| query.result
+    );
+    assert_eq!(depth as usize, assigned);
+
+    // trees with children of other types get their `*Tree` trait too, whose
+    // nodes borrow these children
+    let formula = || Formula::block(vec![assign("x", lit(1)), assign("y", lit(2))], Formula::add(lit(3), lit(4)));
+    assert!(matches!(formula().as_ref(), Term::Block(stmts, _) if stmts.len() == 2));
+    assert_eq!(4, formula().iter_preorder().count());
+    assert_eq!(7, Formula::add(lit(3), lit(4)).fold(&mut |term: Term<Script, i64>| match term {
+        Term::Lit(value) => value,
+        Term::Add(a, b) => a + b,
+        Term::Block(_, value) => value
+    }));
+    assert!(formula().tree_eq(&formula()));
+    assert!(!formula().tree_eq(&Formula::block(vec![assign("x", lit(1))], Formula::add(lit(3), lit(4)))));
+    let mut assigned = Assigned::default();
+    assigned.visit(&Script::seq(vec![assign("x", lit(1)), Script::seq(vec![assign("y", lit(2))])]));
+    assert_eq!("xy", assigned.0);
+
+    let mut arena = Arena::new();
+    let one = arena.push(Term::Lit(1));
+    let root = arena.push(Term::Block(vec!["x".to_string()], one));
+    assert!(matches!(arena.tree(root).as_ref(), Term::Block(stmts, _) if stmts == vec![&"x".to_string()]));
+    assert_eq!(2, arena.tree(root).iter_preorder().count());
+
+    let forest = || Forest::node(vec![Forest::leaf(1), Forest::node(vec![Forest::leaf(2), Forest::leaf(3)])]);
+    let mut leaves = Leaves::default();
+    leaves.visit(&forest());
+    assert_eq!(3, leaves.0);
+    assert_eq!(forest(), Replant.fold(forest()));
+
+    let route = Route::join(Route::crate_(), Route::join(Route::super_(2), Route::crate_()));
+    let mut crates = Crates::default();
+    crates.visit(&route);
+    assert_eq!(2, crates.0);
+    assert_eq!(Segment::<Route>::Crate(Crate), Segment::from(Crate));
 }
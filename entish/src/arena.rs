@@ -0,0 +1,183 @@
+//! Storing trees in a single allocation.
+//!
+//! An [Arena](struct.Arena.html) stores nodes whose children are
+//! [NodeId](../struct.NodeId.html)s, such as `Arithmetic<NodeId>`, in a
+//! `Vec`, instead of behind a pointer each. A tree is moved into an arena by
+//! folding it with `arena.push`, and read back through an
+//! [ArenaRef](type.ArenaRef.html) handle, which implements the `*Tree`
+//! trait generated by Entish when the node derives `Map`. In particular,
+//! folding the handle with `From` copies the tree back out of the arena.
+//!
+//! The handles to trees in an arena and in an
+//! [Interner](../intern/struct.Interner.html) are both a
+//! [Handle](struct.Handle.html) into a [Store](trait.Store.html) of nodes.
+
+use std::cell::OnceCell;
+use std::fmt;
+
+use crate::NodeId;
+
+/// A store of nodes `N`, whose children are ids of other nodes in the same
+/// store.
+///
+/// ```
+/// #[macro_use] extern crate entish;
+/// use entish::prelude::*;
+/// use entish::arena::Arena;
+///
+/// entish! {
+///     #[derive(Debug, PartialEq, Map, MapOwned)]
+///     #[entish(tree = "Expr")]
+///     enum Arithmetic {
+///         Plus(Self, Self),
+///         Just(i32)
+///     }
+/// }
+///
+/// let mut arena = Arena::new();
+/// let root = Expr::plus(Expr::just(1), Expr::just(2)).fold(&mut |node| arena.push(node));
+/// assert_eq!(arena.len(), 3);
+/// assert_eq!(arena.tree(root).fold(&mut Expr::from), Expr::plus(Expr::just(1), Expr::just(2)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Arena<N> {
+    nodes: Vec<N>
+}
+
+impl<N> Default for Arena<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N> Arena<N> {
+    /// An empty arena.
+    pub fn new() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    /// The number of nodes stored.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no node is stored.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Store `node`, returning its id.
+    pub fn push(&mut self, node: N) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// The node of id `id`.
+    ///
+    /// # Panics
+    /// If `id` was not given by this arena.
+    pub fn get(&self, id: NodeId) -> &N {
+        &self.nodes[id.0]
+    }
+
+    /// Like `get` but mutable.
+    pub fn get_mut(&mut self, id: NodeId) -> &mut N {
+        &mut self.nodes[id.0]
+    }
+
+    /// The tree whose root is the node of id `id`.
+    pub fn tree(&self, id: NodeId) -> ArenaRef<'_, N> {
+        Handle::new(self, id)
+    }
+}
+
+impl<N> Store for Arena<N> {
+    type Node = N;
+
+    fn get(&self, id: NodeId) -> &N {
+        Arena::get(self, id)
+    }
+}
+
+/// A store of nodes whose children are ids of other nodes in the same store,
+/// such as an [Arena](struct.Arena.html) or an
+/// [Interner](../intern/struct.Interner.html).
+pub trait Store {
+    /// The type of the nodes stored.
+    type Node;
+
+    /// The node of id `id`.
+    ///
+    /// # Panics
+    /// If `id` was not given by this store.
+    fn get(&self, id: NodeId) -> &Self::Node;
+}
+
+/// A tree stored in an [Arena](struct.Arena.html), given by the id of its
+/// root.
+pub type ArenaRef<'a, N> = Handle<'a, Arena<N>>;
+
+impl<'a, N> ArenaRef<'a, N> {
+    /// The arena the tree is stored in.
+    pub fn arena(&self) -> &'a Arena<N> {
+        self.store
+    }
+}
+
+/// A tree stored in a [Store](trait.Store.html) `S`, given by the id of its
+/// root.
+pub struct Handle<'a, S: Store> {
+    store: &'a S,
+    id: NodeId,
+    /// Handles to the children, made the first time they are borrowed
+    children: OnceCell<Vec<Handle<'a, S>>>
+}
+
+impl<'a, S: Store> Handle<'a, S> {
+    /// The tree of `store` whose root is the node of id `id`.
+    pub fn new(store: &'a S, id: NodeId) -> Self {
+        Self { store, id, children: OnceCell::new() }
+    }
+
+    /// The id of the root.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The root, whose children are ids.
+    pub fn node(&self) -> &'a S::Node {
+        self.store.get(self.id)
+    }
+
+    /// The store the tree is in.
+    pub fn store(&self) -> &'a S {
+        self.store
+    }
+
+    /// Handles to the children of the root, whose ids are listed from the
+    /// root by `ids`. They are made on the first call and kept for the next
+    /// ones, which is what lets the generated `as_ref` borrow them.
+    pub fn children_with<F>(&self, ids: F) -> &[Self]
+    where
+        F: FnOnce(&S::Node) -> Vec<NodeId>
+    {
+        self.children.get_or_init(|| {
+            ids(self.node())
+                .into_iter()
+                .map(|id| Self::new(self.store, id))
+                .collect()
+        })
+    }
+}
+
+impl<'a, S: Store> Clone for Handle<'a, S> {
+    fn clone(&self) -> Self {
+        Self::new(self.store, self.id)
+    }
+}
+
+impl<'a, S: Store> fmt::Debug for Handle<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle").field("id", &self.id).finish()
+    }
+}
@@ -0,0 +1,87 @@
+//! Comparing and hashing trees without recursion.
+//!
+//! These are the building blocks of the `tree_eq`, `tree_partial_cmp`,
+//! `tree_cmp` and `tree_hash` methods of the `*Tree` traits generated by
+//! Entish. Trees are compared node by node in pre-order, where `unfold`
+//! splits a tree into the shape of its root node, i.e. the node without its
+//! children, and its children.
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// Whether the trees `left` and `right` have equal shapes at every node.
+pub fn eq<'a, T, S, U>(left: &'a T, right: &'a T, unfold: &mut U) -> bool
+where
+    U: FnMut(&'a T) -> (S, Vec<&'a T>),
+    S: PartialEq
+{
+    let mut pairs = vec![(left, right)];
+    while let Some((left, right)) = pairs.pop() {
+        let (left_shape, left_children) = unfold(left);
+        let (right_shape, right_children) = unfold(right);
+        if left_shape != right_shape || left_children.len() != right_children.len() {
+            return false
+        }
+        pairs.extend(left_children.into_iter().zip(right_children));
+    }
+    true
+}
+
+/// Compare the trees `left` and `right` by the shapes of their nodes in
+/// pre-order, or `None` if two of them cannot be compared.
+pub fn partial_cmp<'a, T, S, U>(left: &'a T, right: &'a T, unfold: &mut U) -> Option<Ordering>
+where
+    U: FnMut(&'a T) -> (S, Vec<&'a T>),
+    S: PartialOrd
+{
+    let mut pairs = vec![(left, right)];
+    while let Some((left, right)) = pairs.pop() {
+        let (left_shape, left_children) = unfold(left);
+        let (right_shape, right_children) = unfold(right);
+        match left_shape.partial_cmp(&right_shape)? {
+            Ordering::Equal => {},
+            ordering => return Some(ordering)
+        }
+        match left_children.len().cmp(&right_children.len()) {
+            Ordering::Equal => {},
+            ordering => return Some(ordering)
+        }
+        pairs.extend(left_children.into_iter().zip(right_children).rev());
+    }
+    Some(Ordering::Equal)
+}
+
+/// Compare the trees `left` and `right` by the shapes of their nodes in
+/// pre-order.
+pub fn cmp<'a, T, S, U>(left: &'a T, right: &'a T, unfold: &mut U) -> Ordering
+where
+    U: FnMut(&'a T) -> (S, Vec<&'a T>),
+    S: Ord
+{
+    let mut pairs = vec![(left, right)];
+    while let Some((left, right)) = pairs.pop() {
+        let (left_shape, left_children) = unfold(left);
+        let (right_shape, right_children) = unfold(right);
+        match left_shape.cmp(&right_shape).then(left_children.len().cmp(&right_children.len())) {
+            Ordering::Equal => {},
+            ordering => return ordering
+        }
+        pairs.extend(left_children.into_iter().zip(right_children).rev());
+    }
+    Ordering::Equal
+}
+
+/// Feed the shapes of the nodes of the tree `root` in pre-order to `state`.
+pub fn hash<'a, T, S, U, H>(root: &'a T, unfold: &mut U, state: &mut H)
+where
+    U: FnMut(&'a T) -> (S, Vec<&'a T>),
+    S: Hash,
+    H: Hasher
+{
+    let mut trees = vec![root];
+    while let Some(tree) = trees.pop() {
+        let (shape, children) = unfold(tree);
+        shape.hash(state);
+        trees.extend(children.into_iter().rev());
+    }
+}
@@ -0,0 +1,595 @@
+//! The traits of Entish for the containers of the standard library other
+//! than `Vec`, `Option` and `Arc`, so that children can be held in `Box`,
+//! arrays, `VecDeque`, `BTreeMap`, `HashMap` and `Result`. Maps are mapped
+//! over their values, in the order of their keys, and a `Result` over what
+//! it holds when it is `Ok`.
+//!
+//! A `HashMap` does not iterate in the order of its keys, nor in the same
+//! order as a map built from it, so its entries are sorted by key first.
+//! Otherwise the outputs for its children, taken out and put back in the
+//! order they are mapped in, would not end up under their keys.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+use crate::{ForEach, IntoOption, IntoResult, IntoValidated, Map, MapMut, MapOwned, Traverse, Zip};
+
+/// Collect `results`, keeping the errors of all of them.
+fn validate<T, O, E, C>(results: T) -> Result<C, Vec<E>>
+where
+    T: IntoIterator<Item = Result<O, Vec<E>>>,
+    C: std::iter::FromIterator<O>
+{
+    let mut errors = Vec::new();
+    let outputs = results
+        .into_iter()
+        .filter_map(|output| output.map_err(|e| errors.extend(e)).ok())
+        .collect();
+    if errors.is_empty() {
+        Ok(outputs)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The `entries` of a map, in the order of their keys.
+fn by_key<K: Ord, V, T>(entries: T) -> Vec<(K, V)>
+where
+    T: IntoIterator<Item = (K, V)>
+{
+    let mut entries: Vec<(K, V)> = entries.into_iter().collect();
+    entries.sort_unstable_by(|(k, _), (l, _)| k.cmp(l));
+    entries
+}
+
+/// Make an array of the `N` items of `items`.
+fn into_array<T, const N: usize>(items: Vec<T>) -> [T; N] {
+    match items.try_into() {
+        Ok(array) => array,
+        Err(_) => unreachable!("as many items as in the array they come from")
+    }
+}
+
+// Box
+
+impl<'a, I: 'a, O: 'a> Map<'a, &'a I, O> for Box<I> {
+    type OuterO = Box<O>;
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        Box::new(f(self))
+    }
+}
+
+impl<I, O> MapOwned<I, O> for Box<I> {
+    type OuterO = Box<O>;
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        Box::new(f(*self))
+    }
+}
+
+impl<I, O> Traverse<I, O> for Box<I> {
+    type OuterO = Box<O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        f(*self).map(Box::new)
+    }
+}
+
+impl<'a, I: 'a, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for Box<I> {
+    type Other = Box<J>;
+    type OuterO = Box<O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        Some(Box::new(f(self, other)))
+    }
+}
+
+impl<O, E> IntoResult<Box<O>, E> for Box<Result<O, E>> {
+    fn into_result(self) -> Result<Box<O>, E> {
+        (*self).map(Box::new)
+    }
+}
+
+impl<O> IntoOption<Box<O>> for Box<Option<O>> {
+    fn into_option(self) -> Option<Box<O>> {
+        (*self).map(Box::new)
+    }
+}
+
+impl<O, E> IntoValidated<Box<O>, E> for Box<Result<O, Vec<E>>> {
+    fn into_validated(self) -> Result<Box<O>, Vec<E>> {
+        (*self).map(Box::new)
+    }
+}
+
+// Arc
+
+/// Clones the inner result if it is shared with other pointers.
+impl<O: Clone, E: Clone> IntoResult<Arc<O>, E> for Arc<Result<O, E>> {
+    fn into_result(self) -> Result<Arc<O>, E> {
+        Arc::try_unwrap(self)
+            .unwrap_or_else(|arc| (*arc).clone())
+            .map(Arc::new)
+    }
+}
+
+/// Clones the inner option if it is shared with other pointers.
+impl<O: Clone> IntoOption<Arc<O>> for Arc<Option<O>> {
+    fn into_option(self) -> Option<Arc<O>> {
+        Arc::try_unwrap(self)
+            .unwrap_or_else(|arc| (*arc).clone())
+            .map(Arc::new)
+    }
+}
+
+/// Clones the inner result if it is shared with other pointers.
+impl<O: Clone, E: Clone> IntoValidated<Arc<O>, E> for Arc<Result<O, Vec<E>>> {
+    fn into_validated(self) -> Result<Arc<O>, Vec<E>> {
+        Arc::try_unwrap(self)
+            .unwrap_or_else(|arc| (*arc).clone())
+            .map(Arc::new)
+    }
+}
+
+// Arrays
+
+impl<'a, I, O: 'a, const N: usize> Map<'a, &'a I, O> for [I; N] {
+    type OuterO = [O; N];
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        self.each_ref().map(f)
+    }
+}
+
+impl<I, O, const N: usize> MapOwned<I, O> for [I; N] {
+    type OuterO = [O; N];
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        self.map(f)
+    }
+}
+
+impl<I, const N: usize> MapMut<I> for [I; N] {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        self.iter_mut().for_each(f)
+    }
+}
+
+impl<I, const N: usize> ForEach<I> for [I; N] {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        self.iter().for_each(f)
+    }
+}
+
+impl<I, O, const N: usize> Traverse<I, O> for [I; N] {
+    type OuterO = [O; N];
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        IntoIterator::into_iter(self).map(f).collect::<Result<Vec<_>, _>>().map(into_array)
+    }
+}
+
+impl<'a, I, J: 'a, O: 'a, const N: usize> Zip<'a, &'a I, &'a J, O> for [I; N] {
+    type Other = [J; N];
+    type OuterO = [O; N];
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        let mut other = other.iter();
+        Some(self.each_ref().map(|i| f(i, other.next().unwrap())))
+    }
+}
+
+impl<O, E, const N: usize> IntoResult<[O; N], E> for [Result<O, E>; N] {
+    fn into_result(self) -> Result<[O; N], E> {
+        IntoIterator::into_iter(self).collect::<Result<Vec<_>, _>>().map(into_array)
+    }
+}
+
+impl<O, const N: usize> IntoOption<[O; N]> for [Option<O>; N] {
+    fn into_option(self) -> Option<[O; N]> {
+        IntoIterator::into_iter(self).collect::<Option<Vec<_>>>().map(into_array)
+    }
+}
+
+impl<O, E, const N: usize> IntoValidated<[O; N], E> for [Result<O, Vec<E>>; N] {
+    fn into_validated(self) -> Result<[O; N], Vec<E>> {
+        validate::<_, _, _, Vec<_>>(IntoIterator::into_iter(self)).map(into_array)
+    }
+}
+
+// VecDeque
+
+impl<'a, I, O: 'a> Map<'a, &'a I, O> for VecDeque<I> {
+    type OuterO = VecDeque<O>;
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        self.iter().map(f).collect()
+    }
+}
+
+impl<I, O> MapOwned<I, O> for VecDeque<I> {
+    type OuterO = VecDeque<O>;
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        self.into_iter().map(f).collect()
+    }
+}
+
+impl<I> MapMut<I> for VecDeque<I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        self.iter_mut().for_each(f)
+    }
+}
+
+impl<I> ForEach<I> for VecDeque<I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        self.iter().for_each(f)
+    }
+}
+
+impl<I, O> Traverse<I, O> for VecDeque<I> {
+    type OuterO = VecDeque<O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        self.into_iter().map(f).collect()
+    }
+}
+
+impl<'a, I, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for VecDeque<I> {
+    type Other = VecDeque<J>;
+    type OuterO = VecDeque<O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        if self.len() != other.len() {
+            return None
+        }
+        Some(self.iter().zip(other.iter()).map(|(i, j)| f(i, j)).collect())
+    }
+}
+
+impl<O, E> IntoResult<VecDeque<O>, E> for VecDeque<Result<O, E>> {
+    fn into_result(self) -> Result<VecDeque<O>, E> {
+        self.into_iter().collect()
+    }
+}
+
+impl<O> IntoOption<VecDeque<O>> for VecDeque<Option<O>> {
+    fn into_option(self) -> Option<VecDeque<O>> {
+        self.into_iter().collect()
+    }
+}
+
+impl<O, E> IntoValidated<VecDeque<O>, E> for VecDeque<Result<O, Vec<E>>> {
+    fn into_validated(self) -> Result<VecDeque<O>, Vec<E>> {
+        validate(self)
+    }
+}
+
+// BTreeMap
+
+impl<'a, K: Clone + Ord + 'a, I, O: 'a> Map<'a, &'a I, O> for BTreeMap<K, I> {
+    type OuterO = BTreeMap<K, O>;
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        self.iter().map(|(k, i)| (k.clone(), f(i))).collect()
+    }
+}
+
+impl<K: Ord, I, O> MapOwned<I, O> for BTreeMap<K, I> {
+    type OuterO = BTreeMap<K, O>;
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        self.into_iter().map(|(k, i)| (k, f(i))).collect()
+    }
+}
+
+impl<K, I> MapMut<I> for BTreeMap<K, I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        self.values_mut().for_each(f)
+    }
+}
+
+impl<K, I> ForEach<I> for BTreeMap<K, I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        self.values().for_each(f)
+    }
+}
+
+impl<K: Ord, I, O> Traverse<I, O> for BTreeMap<K, I> {
+    type OuterO = BTreeMap<K, O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        self.into_iter().map(|(k, i)| Ok((k, f(i)?))).collect()
+    }
+}
+
+/// The maps have the same shape if they have the same keys.
+impl<'a, K: Clone + Ord + 'a, I, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for BTreeMap<K, I> {
+    type Other = BTreeMap<K, J>;
+    type OuterO = BTreeMap<K, O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        if self.len() != other.len() {
+            return None
+        }
+        self.iter()
+            .zip(other.iter())
+            .map(|((k, i), (l, j))| if k == l { Some((k.clone(), f(i, j))) } else { None })
+            .collect()
+    }
+}
+
+impl<K: Ord, O, E> IntoResult<BTreeMap<K, O>, E> for BTreeMap<K, Result<O, E>> {
+    fn into_result(self) -> Result<BTreeMap<K, O>, E> {
+        self.into_iter().map(|(k, o)| Ok((k, o?))).collect()
+    }
+}
+
+impl<K: Ord, O> IntoOption<BTreeMap<K, O>> for BTreeMap<K, Option<O>> {
+    fn into_option(self) -> Option<BTreeMap<K, O>> {
+        self.into_iter().map(|(k, o)| Some((k, o?))).collect()
+    }
+}
+
+impl<K: Ord, O, E> IntoValidated<BTreeMap<K, O>, E> for BTreeMap<K, Result<O, Vec<E>>> {
+    fn into_validated(self) -> Result<BTreeMap<K, O>, Vec<E>> {
+        validate(self.into_iter().map(|(k, o)| o.map(|o| (k, o))))
+    }
+}
+
+// HashMap
+
+impl<'a, K, I, O: 'a, S> Map<'a, &'a I, O> for HashMap<K, I, S>
+where
+    K: Clone + Ord + Hash + 'a,
+    S: BuildHasher + Default + 'a
+{
+    type OuterO = HashMap<K, O, S>;
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        by_key(self).into_iter().map(|(k, i)| (k.clone(), f(i))).collect()
+    }
+}
+
+impl<K: Ord + Hash, I, O, S: BuildHasher + Default> MapOwned<I, O> for HashMap<K, I, S> {
+    type OuterO = HashMap<K, O, S>;
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        by_key(self).into_iter().map(|(k, i)| (k, f(i))).collect()
+    }
+}
+
+impl<K: Ord, I, S> MapMut<I> for HashMap<K, I, S> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        by_key(self).into_iter().for_each(|(_, i)| f(i))
+    }
+}
+
+impl<K: Ord, I, S> ForEach<I> for HashMap<K, I, S> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        by_key(self).into_iter().for_each(|(_, i)| f(i))
+    }
+}
+
+impl<K: Ord + Hash, I, O, S: BuildHasher + Default> Traverse<I, O> for HashMap<K, I, S> {
+    type OuterO = HashMap<K, O, S>;
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        by_key(self).into_iter().map(|(k, i)| Ok((k, f(i)?))).collect()
+    }
+}
+
+/// The maps have the same shape if they have the same keys, and their
+/// children are paired up by key.
+impl<'a, K, I, J: 'a, O: 'a, S> Zip<'a, &'a I, &'a J, O> for HashMap<K, I, S>
+where
+    K: Clone + Ord + Hash + 'a,
+    S: BuildHasher + Default + 'a
+{
+    type Other = HashMap<K, J, S>;
+    type OuterO = HashMap<K, O, S>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        if self.len() != other.len() {
+            return None
+        }
+        by_key(self).into_iter().map(|(k, i)| Some((k.clone(), f(i, other.get(k)?)))).collect()
+    }
+}
+
+impl<K: Ord + Hash, O, E, S: BuildHasher + Default> IntoResult<HashMap<K, O, S>, E> for HashMap<K, Result<O, E>, S> {
+    fn into_result(self) -> Result<HashMap<K, O, S>, E> {
+        by_key(self).into_iter().map(|(k, o)| Ok((k, o?))).collect()
+    }
+}
+
+impl<K: Ord + Hash, O, S: BuildHasher + Default> IntoOption<HashMap<K, O, S>> for HashMap<K, Option<O>, S> {
+    fn into_option(self) -> Option<HashMap<K, O, S>> {
+        by_key(self).into_iter().map(|(k, o)| Some((k, o?))).collect()
+    }
+}
+
+impl<K: Ord + Hash, O, E, S: BuildHasher + Default> IntoValidated<HashMap<K, O, S>, E> for HashMap<K, Result<O, Vec<E>>, S> {
+    fn into_validated(self) -> Result<HashMap<K, O, S>, Vec<E>> {
+        validate(by_key(self).into_iter().map(|(k, o)| o.map(|o| (k, o))))
+    }
+}
+
+// Result
+
+/// Clones the error if there is one.
+impl<'a, I, O: 'a, X: Clone + 'a> Map<'a, &'a I, O> for Result<I, X> {
+    type OuterO = Result<O, X>;
+    fn map<F>(&'a self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(&'a I) -> O
+    {
+        match self {
+            Ok(i) => Ok(f(i)),
+            Err(x) => Err(x.clone())
+        }
+    }
+}
+
+impl<I, O, X> MapOwned<I, O> for Result<I, X> {
+    type OuterO = Result<O, X>;
+    fn map_owned<F>(self, f: &mut F) -> Self::OuterO
+    where
+        F: FnMut(I) -> O
+    {
+        self.map(f)
+    }
+}
+
+impl<I, X> MapMut<I> for Result<I, X> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        if let Ok(i) = self {
+            f(i)
+        }
+    }
+}
+
+impl<I, X> ForEach<I> for Result<I, X> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        if let Ok(i) = self {
+            f(i)
+        }
+    }
+}
+
+impl<I, O, X> Traverse<I, O> for Result<I, X> {
+    type OuterO = Result<O, X>;
+    fn try_traverse<E, F>(self, f: &mut F) -> Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> Result<O, E>
+    {
+        match self {
+            Ok(i) => f(i).map(Ok),
+            Err(x) => Ok(Err(x))
+        }
+    }
+}
+
+/// Both results have the same shape if they are both `Ok`, or both `Err`
+/// with equal errors, like the other fields of nodes.
+impl<'a, I, J: 'a, O: 'a, X: Clone + PartialEq + 'a> Zip<'a, &'a I, &'a J, O> for Result<I, X> {
+    type Other = Result<J, X>;
+    type OuterO = Result<O, X>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        match (self, other) {
+            (Ok(i), Ok(j)) => Some(Ok(f(i, j))),
+            (Err(x), Err(y)) if x == y => Some(Err(x.clone())),
+            _ => None
+        }
+    }
+}
+
+impl<O, E, X> IntoResult<Result<O, X>, E> for Result<Result<O, E>, X> {
+    fn into_result(self) -> Result<Result<O, X>, E> {
+        match self {
+            Ok(o) => o.map(Ok),
+            Err(x) => Ok(Err(x))
+        }
+    }
+}
+
+impl<O, X> IntoOption<Result<O, X>> for Result<Option<O>, X> {
+    fn into_option(self) -> Option<Result<O, X>> {
+        self.transpose()
+    }
+}
+
+impl<O, E, X> IntoValidated<Result<O, X>, E> for Result<Result<O, Vec<E>>, X> {
+    fn into_validated(self) -> Result<Result<O, X>, Vec<E>> {
+        match self {
+            Ok(o) => o.map(Ok),
+            Err(x) => Ok(Err(x))
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Reducing and visiting trees without recursion.
+//!
+//! These are the building blocks of the `fold`, `try_fold` and `transform_*`
+//! methods of the `*Tree` traits generated by Entish. They only use the heap
+//! to keep track of the nodes left to visit, so they can be used on trees of
+//! any depth without overflowing the stack.
+
+use std::convert::Infallible;
+
+use crate::MapMut;
+
+enum Step<T, S> {
+    /// Split this tree into its shape and children
+    Visit(T),
+    /// Rebuild a node from this shape and the outputs for its last `usize`
+    /// children
+    Build(S, usize)
+}
+
+fn never<O>(out: Result<O, Infallible>) -> O {
+    match out {
+        Ok(out) => out,
+        Err(never) => match never {}
+    }
+}
+
+/// Reduce the tree `root` to a single value, from leaves to root.
+///
+/// `unfold` splits a tree into the shape of its root node and its children,
+/// in order. `build` is then given the shape back along with the outputs
+/// for all the children, in the same order. Nodes are built in the same order
+/// as in a recursive post-order traversal.
+pub fn fold<T, S, O, U, B>(root: T, unfold: &mut U, build: &mut B) -> O
+where
+    U: FnMut(T) -> (S, Vec<T>),
+    B: FnMut(S, Vec<O>) -> O
+{
+    fold_with(root, &mut |tree| tree, unfold, build)
+}
+
+/// Like [fold](fn.fold.html) but applies `pre` to every tree before it is
+/// unfolded, from root to leaves.
+pub fn fold_with<T, S, O, P, U, B>(root: T, pre: &mut P, unfold: &mut U, build: &mut B) -> O
+where
+    P: FnMut(T) -> T,
+    U: FnMut(T) -> (S, Vec<T>),
+    B: FnMut(S, Vec<O>) -> O
+{
+    never(try_fold_with(
+        root,
+        &mut |tree| Ok(pre(tree)),
+        unfold,
+        &mut |shape, outputs| Ok(build(shape, outputs))
+    ))
+}
+
+/// Like [fold](fn.fold.html) but when `build` can fail. Stops at the first
+/// error.
+pub fn try_fold<T, S, O, E, U, B>(root: T, unfold: &mut U, build: &mut B) -> Result<O, E>
+where
+    U: FnMut(T) -> (S, Vec<T>),
+    B: FnMut(S, Vec<O>) -> Result<O, E>
+{
+    try_fold_with(root, &mut Ok, unfold, build)
+}
+
+/// Like [fold_with](fn.fold_with.html) but when `pre` or `build` can fail.
+/// Stops at the first error.
+pub fn try_fold_with<T, S, O, E, P, U, B>(
+    root: T,
+    pre: &mut P,
+    unfold: &mut U,
+    build: &mut B
+) -> Result<O, E>
+where
+    P: FnMut(T) -> Result<T, E>,
+    U: FnMut(T) -> (S, Vec<T>),
+    B: FnMut(S, Vec<O>) -> Result<O, E>
+{
+    let mut steps = vec![Step::Visit(root)];
+    let mut outputs = Vec::new();
+
+    while let Some(step) = steps.pop() {
+        let out = match step {
+            Step::Visit(tree) => pre(tree).map(|tree| {
+                let (shape, children) = unfold(tree);
+                steps.push(Step::Build(shape, children.len()));
+                steps.extend(children.into_iter().rev().map(Step::Visit));
+            }),
+            Step::Build(shape, n_children) => {
+                let children = outputs.split_off(outputs.len() - n_children);
+                build(shape, children).map(|output| outputs.push(output))
+            }
+        };
+
+        if let Err(err) = out {
+            // take apart the trees left to visit one node at a time, so
+            // that dropping them does not recurse
+            while let Some(step) = steps.pop() {
+                if let Step::Visit(tree) = step {
+                    let (_, children) = unfold(tree);
+                    steps.extend(children.into_iter().map(Step::Visit));
+                }
+            }
+            return Err(err)
+        }
+    }
+
+    Ok(outputs.pop().expect("the root of the tree was built"))
+}
+
+/// Apply `f` in place to `root` and every tree under it, from root to
+/// leaves. The children of a tree are the inner trees of its
+/// [MapMut](../trait.MapMut.html) implementation.
+pub fn for_each_mut<T, F>(root: &mut T, f: &mut F)
+where
+    T: MapMut<T>,
+    F: FnMut(&mut T)
+{
+    let mut trees = vec![root];
+    while let Some(tree) = trees.pop() {
+        f(tree);
+        let n_trees = trees.len();
+        tree.map_mut(&mut |child| trees.push(child));
+        trees[n_trees..].reverse();
+    }
+}
+
+/// Like [for_each_mut](fn.for_each_mut.html) but from leaves to root, so
+/// that the children of a tree have been visited when `f` is applied to it.
+///
+/// ```
+/// use entish::MapMut;
+/// use entish::fold::for_each_mut_up;
+///
+/// struct Node {
+///     size: usize,
+///     children: Vec<Node>
+/// }
+///
+/// impl MapMut<Node> for Node {
+///     fn map_mut<'a, F>(&'a mut self, f: &mut F)
+///     where
+///         F: FnMut(&'a mut Node),
+///         Node: 'a
+///     {
+///         self.children.map_mut(f)
+///     }
+/// }
+///
+/// let leaf = || Node { size: 0, children: Vec::new() };
+/// let mut root = Node { size: 0, children: vec![leaf(), Node { size: 0, children: vec![leaf()] }] };
+/// // the size of a tree depends on the ones of its children
+/// for_each_mut_up(&mut root, &mut |node: &mut Node| {
+///     node.size = 1 + node.children.iter().map(|child| child.size).sum::<usize>()
+/// });
+/// assert_eq!((4, 2), (root.size, root.children[1].size));
+/// // `f` may also drop the children it is done with
+/// for_each_mut_up(&mut root, &mut |node: &mut Node| node.children.retain(|child| child.size > 1));
+/// assert_eq!(1, root.children.len());
+/// assert!(root.children[0].children.is_empty());
+/// ```
+pub fn for_each_mut_up<T, F>(root: &mut T, f: &mut F)
+where
+    T: MapMut<T>,
+    F: FnMut(&mut T)
+{
+    // A tree cannot be borrowed mutably along with the trees under it, which
+    // `f` needs once per tree, after the ones under it. Without recursion,
+    // this takes raw pointers, which the `cargo miri test` CI job checks.
+    //
+    // Trees in pre-order, with children from right to left: a tree comes
+    // before all the trees under it, so the reverse order visits children
+    // first.
+    let mut order: Vec<*mut T> = Vec::new();
+    let mut trees: Vec<*mut T> = vec![root];
+    while let Some(tree) = trees.pop() {
+        order.push(tree);
+        // SAFETY: `tree` is `root`, or was borrowed from its parent by the
+        // `map_mut` of an earlier iteration, after which the parent was not
+        // accessed again. The other pointers in `trees` are to trees
+        // disjoint from `tree`, so that this is its only live borrow.
+        let tree = unsafe { &mut *tree };
+        tree.map_mut(&mut |child| trees.push(child));
+    }
+
+    for tree in order.into_iter().rev() {
+        // SAFETY: `tree` is still valid, since `f` was only applied to the
+        // trees under it or disjoint from it, which cannot free it. The
+        // borrows of the trees under it from phase one are not used anymore,
+        // so that reborrowing `tree` invalidates nothing live, and `f` may
+        // drop these trees.
+        f(unsafe { &mut *tree });
+    }
+}
@@ -0,0 +1,158 @@
+//! Sharing identical subtrees by interning nodes.
+//!
+//! An [Interner](struct.Interner.html) stores nodes whose children are
+//! [NodeId](../struct.NodeId.html)s, such as `Arithmetic<NodeId>`, at most
+//! once: interning a node equal to one already stored gives back the id of
+//! that one. Since children are interned before their parents, structurally
+//! equal trees get equal ids, which makes comparing them O(1). Any tree is
+//! interned by folding it with `interner.intern`, and read back through an
+//! [Interned](type.Interned.html) handle, which implements the `*Tree`
+//! trait generated by Entish when the node derives `Map`. The nodes of an
+//! interner can also be read as an [Arena](../arena/struct.Arena.html).
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use crate::NodeId;
+use crate::arena::{Arena, Handle, Store};
+
+/// A store of unique nodes `N`, whose children are ids of other nodes in the
+/// same store.
+///
+/// ```
+/// #[macro_use] extern crate entish;
+/// use entish::prelude::*;
+/// use entish::intern::Interner;
+///
+/// entish! {
+///     #[derive(PartialEq, Eq, Hash, Map, MapOwned)]
+///     #[entish(tree = "Expr")]
+///     enum Arithmetic {
+///         Plus(Self, Self),
+///         Just(i32)
+///     }
+/// }
+///
+/// let sum = || Expr::plus(Expr::just(1), Expr::just(2));
+/// let mut interner = Interner::new();
+/// let left = sum().fold(&mut |node| interner.intern(node));
+/// let right = sum().fold(&mut |node| interner.intern(node));
+/// let both = interner.intern(Arithmetic::Plus(left, right));
+/// assert_eq!(left, right);
+/// assert_eq!(interner.len(), 3 + 1);
+///
+/// let value = interner.tree(both).fold(&mut |node: Arithmetic<i32>| match node {
+///     Arithmetic::Plus(left, right) => left + right,
+///     Arithmetic::Just(value) => value
+/// });
+/// assert_eq!(value, 6);
+/// ```
+pub struct Interner<N> {
+    nodes: Arena<N>,
+    /// The ids of the nodes with a given hash
+    buckets: HashMap<u64, Vec<NodeId>>,
+    state: RandomState
+}
+
+impl<N> Default for Interner<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N> Interner<N> {
+    /// An empty interner.
+    pub fn new() -> Self {
+        Self {
+            nodes: Arena::new(),
+            buckets: HashMap::new(),
+            state: RandomState::new()
+        }
+    }
+
+    /// The number of unique nodes stored.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether no node is stored.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The node of id `id`.
+    ///
+    /// # Panics
+    /// If `id` was not given by this interner.
+    pub fn get(&self, id: NodeId) -> &N {
+        self.nodes.get(id)
+    }
+
+    /// The nodes stored, in the order they were first interned.
+    pub fn arena(&self) -> &Arena<N> {
+        &self.nodes
+    }
+
+    /// The tree whose root is the node of id `id`.
+    pub fn tree(&self, id: NodeId) -> Interned<'_, N> {
+        Handle::new(self, id)
+    }
+}
+
+impl<N> Store for Interner<N> {
+    type Node = N;
+
+    fn get(&self, id: NodeId) -> &N {
+        Interner::get(self, id)
+    }
+}
+
+impl<N: Eq + Hash> Interner<N> {
+    /// The id of `node`, which is stored if no equal node was already.
+    pub fn intern(&mut self, node: N) -> NodeId {
+        let Self { nodes, buckets, state } = self;
+        let bucket = buckets.entry(state.hash_one(&node)).or_default();
+        if let Some(id) = bucket.iter().find(|id| *nodes.get(**id) == node) {
+            return *id
+        }
+        let id = nodes.push(node);
+        bucket.push(id);
+        id
+    }
+
+    /// The id of the node equal to `node`, if there is one.
+    pub fn lookup(&self, node: &N) -> Option<NodeId> {
+        self.buckets
+            .get(&self.state.hash_one(node))?
+            .iter()
+            .find(|id| self.nodes.get(**id) == node)
+            .copied()
+    }
+}
+
+/// A tree stored in an [Interner](struct.Interner.html), given by the id of
+/// its root. Handles are equal if and only if they have the same id in the
+/// same interner, which for interned trees is structural equality.
+pub type Interned<'a, N> = Handle<'a, Interner<N>>;
+
+impl<'a, N> Interned<'a, N> {
+    /// The interner the tree is stored in.
+    pub fn interner(&self) -> &'a Interner<N> {
+        self.store()
+    }
+}
+
+impl<'a, N> PartialEq for Interned<'a, N> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.store(), other.store()) && self.id() == other.id()
+    }
+}
+
+impl<'a, N> Eq for Interned<'a, N> {}
+
+impl<'a, N> Hash for Interned<'a, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state)
+    }
+}
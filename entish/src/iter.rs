@@ -0,0 +1,127 @@
+//! Iterating over all the nodes of a tree.
+//!
+//! These are the iterators returned by the `iter_preorder`,
+//! `iter_postorder` and `iter_bfs` methods of the `*Tree` traits generated
+//! by Entish, and by their `_with_depth` variants, which also yield the depth
+//! of every node, the root being at depth `0`. They keep an explicit stack or
+//! queue and only get the children of a node when they reach it, so they do
+//! not recurse and work on trees of any depth.
+
+use std::collections::VecDeque;
+
+/// Gets the direct children of a tree, see `iter_children`.
+pub type Children<'a, T> = fn(&'a T) -> std::vec::IntoIter<&'a T>;
+
+/// Iterator over the nodes of a tree, with their depths, every node before
+/// its children.
+pub struct PreorderWithDepth<'a, T> {
+    stack: Vec<(usize, &'a T)>,
+    children: Children<'a, T>
+}
+
+impl<'a, T> PreorderWithDepth<'a, T> {
+    pub fn new(root: &'a T, children: Children<'a, T>) -> Self {
+        Self { stack: vec![(0, root)], children }
+    }
+}
+
+impl<'a, T> Iterator for PreorderWithDepth<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, tree) = self.stack.pop()?;
+        self.stack.extend((self.children)(tree).rev().map(|child| (depth + 1, child)));
+        Some((depth, tree))
+    }
+}
+
+/// Iterator over the nodes of a tree, with their depths, every node after
+/// its children.
+pub struct PostorderWithDepth<'a, T> {
+    /// The nodes from the root to the next one, with their children left to
+    /// visit
+    stack: Vec<(usize, &'a T, std::vec::IntoIter<&'a T>)>,
+    children: Children<'a, T>
+}
+
+impl<'a, T> PostorderWithDepth<'a, T> {
+    pub fn new(root: &'a T, children: Children<'a, T>) -> Self {
+        Self { stack: vec![(0, root, children(root))], children }
+    }
+}
+
+impl<'a, T> Iterator for PostorderWithDepth<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (depth, _, siblings) = self.stack.last_mut()?;
+            let depth = *depth;
+            match siblings.next() {
+                Some(child) => self.stack.push((depth + 1, child, (self.children)(child))),
+                None => return self.stack.pop().map(|(depth, tree, _)| (depth, tree))
+            }
+        }
+    }
+}
+
+/// Iterator over the nodes of a tree, with their depths, level by level from
+/// the root.
+pub struct BfsWithDepth<'a, T> {
+    queue: VecDeque<(usize, &'a T)>,
+    children: Children<'a, T>
+}
+
+impl<'a, T> BfsWithDepth<'a, T> {
+    pub fn new(root: &'a T, children: Children<'a, T>) -> Self {
+        Self { queue: vec![(0, root)].into(), children }
+    }
+}
+
+impl<'a, T> Iterator for BfsWithDepth<'a, T> {
+    type Item = (usize, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, tree) = self.queue.pop_front()?;
+        self.queue.extend((self.children)(tree).map(|child| (depth + 1, child)));
+        Some((depth, tree))
+    }
+}
+
+macro_rules! without_depth {
+    ($(#[$doc:meta])* $name:ident($with_depth:ident)) => {
+        $(#[$doc])*
+        pub struct $name<'a, T>($with_depth<'a, T>);
+
+        impl<'a, T> $name<'a, T> {
+            pub fn new(root: &'a T, children: Children<'a, T>) -> Self {
+                Self($with_depth::new(root, children))
+            }
+        }
+
+        impl<'a, T> Iterator for $name<'a, T> {
+            type Item = &'a T;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.0.next().map(|(_, tree)| tree)
+            }
+        }
+    }
+}
+
+without_depth! {
+    /// Like [PreorderWithDepth](struct.PreorderWithDepth.html) but without
+    /// the depths.
+    Preorder(PreorderWithDepth)
+}
+
+without_depth! {
+    /// Like [PostorderWithDepth](struct.PostorderWithDepth.html) but without
+    /// the depths.
+    Postorder(PostorderWithDepth)
+}
+
+without_depth! {
+    /// Like [BfsWithDepth](struct.BfsWithDepth.html) but without the depths.
+    Bfs(BfsWithDepth)
+}
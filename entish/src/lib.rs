@@ -15,7 +15,7 @@
 //! /// main code-generation process of the crate and is where
 //! /// everything can be customized.
 //! entish! {
-//!     #[derive(Map, From, IntoResult)]
+//!     #[derive(Map, MapOwned, From, IntoResult)]
 //!     #[entish(variants_as_structs)]
 //!     enum Arithmetic {
 //!         Plus {
@@ -39,10 +39,14 @@
 //!     fn as_ref(&self) -> Arithmetic<&Self> {
 //!         self.0.map(&mut |c| c.as_ref())
 //!     }
+//!
+//!     fn into_inner(self) -> Arithmetic<Self> {
+//!         self.0.map_owned(&mut |c| *c)
+//!     }
 //! }
 //! /// After all of this we can do things like this
 //! fn do_arithmetic(node: &Arithmetic<i32>) -> i32 {
-//!     match *op {
+//!     match *node {
 //!         Arithmetic::Plus(Plus { left, right }) => left + right,
 //!         Arithmetic::Times(Times { left, right }) => left * right,
 //!         Arithmetic::Just(Just(v)) => v
@@ -52,7 +56,7 @@
 //! impl Expr
 //! {
 //!     fn compute_value(self) -> i32 {
-//!         self.fold(&mut |op| do_arithmetic)
+//!         self.fold(&mut |node| do_arithmetic(&node))
 //!     }
 //! }
 //! ```
@@ -61,13 +65,88 @@
 //! this example would generate, see the [entish-test](https://docs.rs/entish-test) crate.
 //! ## Usage
 //! - To do.
+//! ## Families
+//! Trees declared in the same `entish! { .. }` block can have children of
+//! each other's types, but cannot have generic parameters:
+//! ```compile_fail
+//! #[macro_use] extern crate entish;
+//!
+//! entish! {
+//!     #[derive(Map, MapOwned)]
+//!     enum Expr<T> {
+//!         Lit(T),
+//!         Block(Vec<Stmt>, Self)
+//!     }
+//!
+//!     #[derive(Map, MapOwned)]
+//!     enum Stmt {
+//!         Assign(String, Expr),
+//!         Seq(Vec<Self>)
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//! ## Other containers
+//! Children can be held in the containers of other crates, such as
+//! `SmallVec`, through a newtype implementing the traits of `entish` the
+//! node derives, as the orphan rule keeps them from being implemented on the
+//! container itself. The default methods of the `*Visit` traits also need
+//! `ForEach`. Here a `Vec` stands for such a container:
+//! ```
+//! #[macro_use] extern crate entish;
+//! use entish::prelude::*;
+//!
+//! #[derive(Debug, PartialEq)]
+//! pub struct Small<T>(Vec<T>);
+//!
+//! impl<'a, I: 'a, O: 'a> Map<'a, &'a I, O> for Small<I> {
+//!     type OuterO = Small<O>;
+//!     fn map<F: FnMut(&'a I) -> O>(&'a self, f: &mut F) -> Small<O> {
+//!         Small(self.0.iter().map(f).collect())
+//!     }
+//! }
+//!
+//! impl<I, O> MapOwned<I, O> for Small<I> {
+//!     type OuterO = Small<O>;
+//!     fn map_owned<F: FnMut(I) -> O>(self, f: &mut F) -> Small<O> {
+//!         Small(self.0.into_iter().map(f).collect())
+//!     }
+//! }
+//!
+//! entish! {
+//!     #[derive(Debug, PartialEq, Map, MapOwned)]
+//!     #[entish(tree = "Rose")]
+//!     enum Node {
+//!         Branch(Small<Self>),
+//!         Leaf(u32)
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let rose = Rose::branch(Small(vec![Rose::leaf(1), Rose::branch(Small(vec![Rose::leaf(2)]))]));
+//! assert_eq!(3, rose.fold(&mut |node: Node<u32>| match node {
+//!     Node::Branch(Small(sums)) => sums.into_iter().sum(),
+//!     Node::Leaf(value) => value
+//! }));
+//! # }
+//! ```
 
+use std::rc::Rc;
 use std::sync::Arc;
 
-#[macro_use] extern crate entish_derive;
 pub use entish_derive::{entish, generate, expr_match, unravel};
 
 pub mod prelude;
+pub mod arena;
+pub mod cmp;
+pub mod fold;
+pub mod intern;
+pub mod iter;
+pub mod path;
+pub mod rewrite;
+pub mod zipper;
+
+mod containers;
 
 /// A trait for types that allow for applying a closure `FnMut(I) -> O`
 /// around an inner `I` and wrapping around the result. See the [provided
@@ -93,6 +172,28 @@ pub trait MapOwned<I, O> {
         F: FnMut(I) -> O;
 }
 
+/// A trait for types that allow for applying a closure `FnMut(&mut I)` to
+/// every inner `I` in place, without rebuilding them.
+pub trait MapMut<I> {
+    /// Apply the closure `f` to a mutable reference to every inner `I`.
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a;
+}
+
+/// A trait for types that allow for applying a closure `FnMut(&I)` to every
+/// inner `I`, without building anything from the outputs. It is what the
+/// default methods of the `*Visit` traits generated by Entish go through
+/// containers of children with.
+pub trait ForEach<I> {
+    /// Apply the closure `f` to a reference to every inner `I`.
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a;
+}
+
 impl<'a, I, O: 'a> Map<'a, &'a I, O> for Vec<I> {
     type OuterO = Vec<O>;
     #[inline]
@@ -155,6 +256,258 @@ impl<I: Clone, O> MapOwned<I, O> for Arc<I> {
     }
 }
 
+impl<I> MapMut<I> for Vec<I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        self.iter_mut().for_each(f)
+    }
+}
+
+impl<I> MapMut<I> for Option<I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        if let Some(i) = self {
+            f(i)
+        }
+    }
+}
+
+impl<I> MapMut<I> for Box<I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        f(self)
+    }
+}
+
+/// Clones the inner `I` if it is shared with other pointers.
+impl<I: Clone> MapMut<I> for Arc<I> {
+    fn map_mut<'a, F>(&'a mut self, f: &mut F)
+    where
+        F: FnMut(&'a mut I),
+        I: 'a
+    {
+        f(Arc::make_mut(self))
+    }
+}
+
+impl<I> ForEach<I> for Vec<I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        self.iter().for_each(f)
+    }
+}
+
+impl<I> ForEach<I> for Option<I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        if let Some(i) = self {
+            f(i)
+        }
+    }
+}
+
+impl<I> ForEach<I> for Box<I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        f(self)
+    }
+}
+
+impl<I> ForEach<I> for Arc<I> {
+    fn for_each<'a, F>(&'a self, f: &mut F)
+    where
+        F: FnMut(&'a I),
+        I: 'a
+    {
+        f(self)
+    }
+}
+
+/// A trait for the effects a closure given to
+/// [Traverse](trait.Traverse.html) can have, such as failing with
+/// `Result<T, E>` or `Option<T>`.
+pub trait Effect<T>: Sized {
+    /// What is left when there is no output, e.g. `E` for `Result<T, E>`.
+    type Residual;
+    /// The same effect around an output of type `U`.
+    type With<U>: Effect<U, Residual = Self::Residual>;
+    /// Wrap a successful output.
+    fn from_output(output: T) -> Self;
+    /// Wrap what is left when there is no output.
+    fn from_residual(residual: Self::Residual) -> Self;
+    /// Split into the output or what is left without one.
+    fn branch(self) -> std::result::Result<T, Self::Residual>;
+}
+
+impl<T, E> Effect<T> for std::result::Result<T, E> {
+    type Residual = E;
+    type With<U> = std::result::Result<U, E>;
+
+    fn from_output(output: T) -> Self {
+        Ok(output)
+    }
+
+    fn from_residual(residual: E) -> Self {
+        Err(residual)
+    }
+
+    fn branch(self) -> std::result::Result<T, E> {
+        self
+    }
+}
+
+impl<T> Effect<T> for Option<T> {
+    type Residual = ();
+    type With<U> = Option<U>;
+
+    fn from_output(output: T) -> Self {
+        Some(output)
+    }
+
+    fn from_residual(_: ()) -> Self {
+        None
+    }
+
+    fn branch(self) -> std::result::Result<T, ()> {
+        self.ok_or(())
+    }
+}
+
+/// A trait for types that allow for applying a closure with an
+/// [Effect](trait.Effect.html), such as `FnMut(I) -> Option<O>`, around an
+/// inner `I` and wrapping around the result, in a single pass. This is like
+/// [MapOwned](trait.MapOwned.html) followed by
+/// [IntoOption](trait.IntoOption.html) or [IntoResult](trait.IntoResult.html).
+pub trait Traverse<I, O>: Sized {
+    type OuterO;
+
+    /// Apply the closure `f` to inner `I`, stopping at the first error.
+    fn try_traverse<E, F>(self, f: &mut F) -> std::result::Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> std::result::Result<O, E>;
+
+    /// Apply the closure `f` to inner `I`, stopping at the first one without
+    /// an output.
+    fn traverse<R, F>(self, f: &mut F) -> R::With<Self::OuterO>
+    where
+        R: Effect<O>,
+        F: FnMut(I) -> R
+    {
+        match self.try_traverse(&mut |i| f(i).branch()) {
+            Ok(output) => Effect::from_output(output),
+            Err(residual) => Effect::from_residual(residual)
+        }
+    }
+}
+
+impl<I, O> Traverse<I, O> for Vec<I> {
+    type OuterO = Vec<O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> std::result::Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> std::result::Result<O, E>
+    {
+        self.into_iter().map(f).collect()
+    }
+}
+
+impl<I, O> Traverse<I, O> for Option<I> {
+    type OuterO = Option<O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> std::result::Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> std::result::Result<O, E>
+    {
+        self.map(f).transpose()
+    }
+}
+
+impl<I: Clone, O> Traverse<I, O> for Arc<I> {
+    type OuterO = Arc<O>;
+    fn try_traverse<E, F>(self, f: &mut F) -> std::result::Result<Self::OuterO, E>
+    where
+        F: FnMut(I) -> std::result::Result<O, E>
+    {
+        f((*self).clone()).map(Arc::new)
+    }
+}
+
+/// A trait for types that allow for pairing up each inner `I` with the
+/// corresponding inner `J` of another value of the same shape, applying a
+/// closure `FnMut(I, J) -> O` to each pair and wrapping around the results.
+/// See the [provided implementation](#foreign-impls) on `Vec<I>` for an
+/// example.
+///
+/// This trait is not meant to be used directly, but instead should be used
+/// through the `.zip_with` method implemented on types generated by Entish.
+pub trait Zip<'a, I: 'a, J: 'a, O: 'a> {
+    /// The type of the other value, like `Self` but wrapping around `J`.
+    type Other: 'a;
+    /// The type obtained after applying a closure to the pairs.
+    type OuterO: 'a;
+    /// Apply the closure `f` to pairs of inner `I` and `J`, or return
+    /// `None` if `other` does not have the same shape as `self`.
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(I, J) -> O;
+}
+
+impl<'a, I, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for Vec<I> {
+    type Other = Vec<J>;
+    type OuterO = Vec<O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        if self.len() != other.len() {
+            return None
+        }
+        Some(self.iter().zip(other.iter()).map(|(i, j)| f(i, j)).collect())
+    }
+}
+
+impl<'a, I, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for Option<I> {
+    type Other = Option<J>;
+    type OuterO = Option<O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        match (self, other) {
+            (Some(i), Some(j)) => Some(Some(f(i, j))),
+            (None, None) => Some(None),
+            _ => None
+        }
+    }
+}
+
+impl<'a, I: 'a, J: 'a, O: 'a> Zip<'a, &'a I, &'a J, O> for Arc<I> {
+    type Other = Arc<J>;
+    type OuterO = Arc<O>;
+    fn zip_with<F>(&'a self, other: &'a Self::Other, f: &mut F) -> Option<Self::OuterO>
+    where
+        F: FnMut(&'a I, &'a J) -> O
+    {
+        Some(Arc::new(f(self, other)))
+    }
+}
+
 /// A trait for types that can convert to a `Result<O, E>`.
 pub trait IntoResult<O, E> {
     fn into_result(self) -> std::result::Result<O, E>;
@@ -184,8 +537,117 @@ impl<O> IntoOption<Vec<O>> for Vec<Option<O>> {
     }
 }
 
-impl<O> IntoOption<O> for Option<O> {
-    fn into_option(self) -> Option<O> {
+impl<O> IntoOption<Option<O>> for Option<Option<O>> {
+    fn into_option(self) -> Option<Option<O>> {
+        match self {
+            Some(o) => o.map(Some),
+            None => Some(None)
+        }
+    }
+}
+
+/// A trait for types that can convert to a `Result<O, Vec<E>>`, keeping
+/// the errors of all their inner results instead of only the first one.
+pub trait IntoValidated<O, E> {
+    fn into_validated(self) -> std::result::Result<O, Vec<E>>;
+}
+
+impl<O, E> IntoValidated<Vec<O>, E> for Vec<std::result::Result<O, Vec<E>>> {
+    fn into_validated(self) -> std::result::Result<Vec<O>, Vec<E>> {
+        let mut errors = Vec::new();
+        let outputs = self
+            .into_iter()
+            .filter_map(|output| output.map_err(|e| errors.extend(e)).ok())
+            .collect();
+        if errors.is_empty() {
+            Ok(outputs)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl<O, E> IntoValidated<Option<O>, E> for Option<std::result::Result<O, Vec<E>>> {
+    fn into_validated(self) -> std::result::Result<Option<O>, Vec<E>> {
+        self.transpose()
+    }
+}
+
+/// An error along with the path to the node it was found at, as returned by
+/// the `try_fold_all` method of the `*Tree` traits generated by Entish.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Located<E> {
+    /// The index of the child to go down into at each level from the root,
+    /// in the order of `iter_children`
+    pub path: Vec<usize>,
+    pub error: E
+}
+
+/// The index of a node stored in an [Arena](arena/struct.Arena.html) or an
+/// [Interner](intern/struct.Interner.html), standing in for the node as one
+/// of the children of another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// The position of the node in the order nodes were added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// A trait for pointer types that can be used as the dynamic indirection
+/// between a node and its children in a tree generated with
+/// `#[entish(tree = "..", indirection = "..")]`.
+pub trait Indirection<T>: std::ops::Deref<Target = T> {
+    /// Put `value` behind the pointer.
+    fn new(value: T) -> Self;
+    /// Take the value from behind the pointer.
+    fn into_inner(self) -> T;
+    /// Get a mutable reference to the value behind the pointer.
+    fn make_mut(&mut self) -> &mut T;
+}
+
+impl<T> Indirection<T> for Box<T> {
+    fn new(value: T) -> Self {
+        Box::new(value)
+    }
+
+    fn into_inner(self) -> T {
+        *self
+    }
+
+    fn make_mut(&mut self) -> &mut T {
         self
     }
 }
+
+/// Clones the value if it is shared with other pointers.
+impl<T: Clone> Indirection<T> for Rc<T> {
+    fn new(value: T) -> Self {
+        Rc::new(value)
+    }
+
+    fn into_inner(self) -> T {
+        Rc::try_unwrap(self).unwrap_or_else(|rc| (*rc).clone())
+    }
+
+    fn make_mut(&mut self) -> &mut T {
+        Rc::make_mut(self)
+    }
+}
+
+/// Clones the value if it is shared with other pointers.
+impl<T: Clone> Indirection<T> for Arc<T> {
+    fn new(value: T) -> Self {
+        Arc::new(value)
+    }
+
+    fn into_inner(self) -> T {
+        Arc::try_unwrap(self).unwrap_or_else(|arc| (*arc).clone())
+    }
+
+    fn make_mut(&mut self) -> &mut T {
+        Arc::make_mut(self)
+    }
+}
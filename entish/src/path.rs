@@ -0,0 +1,211 @@
+//! Addressing the nodes of a tree.
+//!
+//! A [TreePath](struct.TreePath.html) leads from the root of a tree to one of
+//! its nodes, one [Step](enum.Step.html) down at a time. It is what the
+//! `get`, `get_mut` and `replace_at` methods of the `*Tree` traits generated
+//! by Entish take, and what `iter_paths` yields along with every node.
+
+use std::fmt;
+use std::iter::FromIterator;
+
+
+/// A step from a node down to one of its children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Step {
+    /// The child at this index, in the order of `iter_children`
+    Index(usize),
+    /// The child at `index` among the ones held in the field `name`, in the
+    /// order of `iter_children`. For a field holding a single child, `index`
+    /// is `0`. Unnamed fields are named by their index.
+    Field {
+        name: &'static str,
+        index: usize
+    }
+}
+
+impl Step {
+    /// The child this step leads to among the `children` of a node, held in
+    /// the fields named by `fields` as given by `child_fields`.
+    pub fn select<T>(&self, fields: &[&'static str], children: Vec<T>) -> Option<T> {
+        match *self {
+            Step::Index(index) => children.into_iter().nth(index),
+            Step::Field { name, index } => fields
+                .iter()
+                .zip(children)
+                .filter(|(field, _)| **field == name)
+                .nth(index)
+                .map(|(_, child)| child)
+        }
+    }
+}
+
+/// The name of a child of a node, as given by the `children_named` method
+/// generated on nodes. It is displayed as the name of the field holding the
+/// child, followed by its index if the field is a container, e.g. `left` or
+/// `children[2]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChildName {
+    /// The name of the field, or its index if it is unnamed
+    pub field: &'static str,
+    /// The index of the child in the field, if it is a container
+    pub index: Option<usize>
+}
+
+impl ChildName {
+    /// Whether `name` is how this name is displayed.
+    pub fn is(&self, name: &str) -> bool {
+        let rest = match name.strip_prefix(self.field) {
+            Some(rest) => rest,
+            None => return false
+        };
+        match self.index {
+            None => rest.is_empty(),
+            Some(index) => rest
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|index| index.parse().ok())
+                == Some(index)
+        }
+    }
+}
+
+impl fmt::Display for ChildName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            None => write!(f, "{}", self.field),
+            Some(index) => write!(f, "{}[{}]", self.field, index)
+        }
+    }
+}
+
+impl Step {
+    /// The steps down to the `children` of a node, as given by
+    /// `children_named`: `Step::Field` steps for the children in named
+    /// fields, and `Step::Index` steps for the ones in unnamed fields, which
+    /// are named by their index.
+    pub fn to_children<T>(children: Vec<(ChildName, T)>) -> Vec<(Step, T)> {
+        children
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, child))| {
+                let unnamed = name.field.starts_with(|c: char| c.is_ascii_digit());
+                (if unnamed { Step::Index(index) } else { Step::from(name) }, child)
+            })
+            .collect()
+    }
+}
+
+/// The step down to the child of this name.
+impl From<ChildName> for Step {
+    fn from(name: ChildName) -> Self {
+        Step::Field { name: name.field, index: name.index.unwrap_or(0) }
+    }
+}
+
+/// The path from the root of a tree to one of its nodes, as the steps down
+/// from the root.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreePath(Vec<Step>);
+
+impl TreePath {
+    /// The path to the root itself.
+    pub fn root() -> Self {
+        Self(Vec::new())
+    }
+
+    /// The path to the child at `index` of the node at this path.
+    pub fn child(mut self, index: usize) -> Self {
+        self.push(Step::Index(index));
+        self
+    }
+
+    /// The path to the child at `index` in the field `name` of the node at
+    /// this path.
+    pub fn field(mut self, name: &'static str, index: usize) -> Self {
+        self.push(Step::Field { name, index });
+        self
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.0.push(step)
+    }
+
+    pub fn pop(&mut self) -> Option<Step> {
+        self.0.pop()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.0
+    }
+
+    /// The number of steps, i.e. the depth of the node at this path.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether this is the path to the root.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// From the indices of the children to go down into, such as the paths of
+/// [Located](../struct.Located.html) errors.
+impl From<Vec<usize>> for TreePath {
+    fn from(indices: Vec<usize>) -> Self {
+        indices.into_iter().map(Step::Index).collect()
+    }
+}
+
+impl FromIterator<Step> for TreePath {
+    fn from_iter<I: IntoIterator<Item = Step>>(steps: I) -> Self {
+        Self(steps.into_iter().collect())
+    }
+}
+
+/// Gets the direct children of a tree along with their names, see
+/// `children_named`.
+pub type NamedChildren<'a, T> = fn(&'a T) -> Vec<(ChildName, &'a T)>;
+
+/// Iterator over the nodes of a tree along with their paths from the root,
+/// every node before its children. Paths are made of the steps given by
+/// [Step::to_children](enum.Step.html#method.to_children), so that they name
+/// the fields they go through when they are named. The path of the last node
+/// yielded is kept and only changed at its end to get the next one, so that
+/// each path is built once, when yielded.
+pub struct Paths<'a, T> {
+    /// The trees left to visit, with their depth and the step down to them
+    /// from their parent
+    stack: Vec<(usize, Step, &'a T)>,
+    /// The path to the last node yielded, whose first steps lead to the
+    /// parents of the trees left to visit
+    path: TreePath,
+    children: NamedChildren<'a, T>
+}
+
+impl<'a, T> Paths<'a, T> {
+    pub fn new(root: &'a T, children: NamedChildren<'a, T>) -> Self {
+        Self { stack: vec![(0, Step::Index(0), root)], path: TreePath::root(), children }
+    }
+}
+
+impl<'a, T> Iterator for Paths<'a, T> {
+    type Item = (TreePath, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, step, tree) = self.stack.pop()?;
+        if depth > 0 {
+            // the path to the parent, then down to the tree
+            self.path.0.truncate(depth - 1);
+            self.path.push(step);
+        }
+        let children = Step::to_children((self.children)(tree));
+        self.stack.extend(
+            children
+                .into_iter()
+                .rev()
+                .map(|(step, child)| (depth + 1, step, child))
+        );
+        Some((self.path.clone(), tree))
+    }
+}
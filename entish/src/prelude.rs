@@ -1 +1 @@
-pub use crate::{Map, MapOwned, IntoResult, IntoOption, entish};
+pub use crate::{Map, MapOwned, MapMut, ForEach, Traverse, Zip, IntoResult, IntoOption, IntoValidated, entish};
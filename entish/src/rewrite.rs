@@ -0,0 +1,139 @@
+//! Rewriting trees with sets of rules.
+//!
+//! A [RuleSet](struct.RuleSet.html) is a list of named rules, each of which
+//! may rewrite a single node. The `rewrite` method of the `*Tree` traits
+//! generated by Entish applies them over a whole tree according to a
+//! [Strategy](enum.Strategy.html), and reports which rules fired.
+
+/// The order in which the nodes of a tree are rewritten in a single pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// From leaves to root, see `transform_up`
+    BottomUp,
+    /// From root to leaves, see `transform_down`
+    TopDown
+}
+
+/// How many passes over the tree to make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// A single pass
+    Once(Order),
+    /// Passes until one of them fires no rule, or the limit of the rule set
+    /// is reached
+    Fixpoint(Order)
+}
+
+type RuleFn<'r, T> = dyn Fn(&T) -> Option<T> + 'r;
+
+struct Rule<'r, T> {
+    name: &'static str,
+    apply: Box<RuleFn<'r, T>>
+}
+
+/// A list of rules rewriting nodes of type `T`, tried in the order they were
+/// added. At every node, the first rule that returns `Some` replaces the node.
+pub struct RuleSet<'r, T> {
+    rules: Vec<Rule<'r, T>>,
+    max_passes: usize
+}
+
+/// What happened while applying a [RuleSet](struct.RuleSet.html) to a tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// How many times each rule fired, in the order the rules were added
+    pub fired: Vec<(&'static str, usize)>,
+    /// The number of passes made over the tree
+    pub passes: usize,
+    /// Whether the last pass fired no rule, i.e. the tree is in normal form
+    pub converged: bool
+}
+
+impl Report {
+    /// How many times the rules named `name` fired.
+    pub fn count(&self, name: &str) -> usize {
+        self.fired
+            .iter()
+            .filter(|(rule, _)| *rule == name)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// How many times any rule fired.
+    pub fn total(&self) -> usize {
+        self.fired.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl<'r, T> Default for RuleSet<'r, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'r, T> RuleSet<'r, T> {
+    /// An empty rule set, making at most 100 passes to reach a fixpoint.
+    pub fn new() -> Self {
+        Self { rules: Vec::new(), max_passes: 100 }
+    }
+
+    /// Add a rule named `name`, which rewrites a node by returning `Some`.
+    pub fn rule<F>(mut self, name: &'static str, apply: F) -> Self
+    where
+        F: Fn(&T) -> Option<T> + 'r
+    {
+        self.rules.push(Rule { name, apply: Box::new(apply) });
+        self
+    }
+
+    /// Set the maximum number of passes made by `Strategy::Fixpoint`.
+    pub fn max_passes(mut self, max_passes: usize) -> Self {
+        self.max_passes = max_passes;
+        self
+    }
+
+    /// Apply the first rule that fires on `node`, with its index.
+    fn apply(&self, node: &T) -> Option<(usize, T)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find_map(|(idx, rule)| (rule.apply)(node).map(|node| (idx, node)))
+    }
+
+    /// Apply the rules to `tree` according to `strategy`. Each pass is made
+    /// with `walk`, which rebuilds a tree in the given order with a node
+    /// rewrite. This is what the generated `rewrite` method calls.
+    pub fn run<W>(&self, mut tree: T, strategy: Strategy, walk: &mut W) -> (T, Report)
+    where
+        W: FnMut(T, Order, &mut dyn FnMut(T) -> T) -> T
+    {
+        let (order, max_passes) = match strategy {
+            Strategy::Once(order) => (order, 1),
+            Strategy::Fixpoint(order) => (order, self.max_passes)
+        };
+        let mut report = Report {
+            fired: self.rules.iter().map(|rule| (rule.name, 0)).collect(),
+            passes: 0,
+            converged: false
+        };
+
+        while report.passes < max_passes {
+            let mut fired = false;
+            tree = walk(tree, order, &mut |node| match self.apply(&node) {
+                Some((idx, node)) => {
+                    report.fired[idx].1 += 1;
+                    fired = true;
+                    node
+                },
+                None => node
+            });
+            report.passes += 1;
+            if !fired {
+                report.converged = true;
+                break
+            }
+        }
+
+        (tree, report)
+    }
+}
@@ -0,0 +1,140 @@
+//! Navigating and editing a tree one node at a time.
+//!
+//! A [Zipper](struct.Zipper.html) focuses on a node of a tree it owns, and
+//! moves the focus down into a child, up to the parent or sideways to a
+//! sibling. The focused node can be edited in place, and the tree is rebuilt
+//! around it on the way back up. It is made by the `zipper` method of the
+//! `*Tree` traits generated by Entish.
+
+use crate::path::TreePath;
+
+/// The node above the focus, with a hole where the focus is.
+struct Frame<T, S> {
+    /// The node without its children
+    shape: S,
+    /// The children before the hole, nearest last
+    left: Vec<T>,
+    /// The children after the hole, nearest last
+    right: Vec<T>
+}
+
+/// A tree of `T` focused on one of its nodes, whose nodes without their
+/// children are `S`.
+pub struct Zipper<T, S> {
+    /// Always `Some` between calls
+    focus: Option<T>,
+    /// The nodes from the root to the parent of the focus
+    frames: Vec<Frame<T, S>>,
+    unfold: fn(T) -> (S, Vec<T>),
+    refill: fn(S, Vec<T>) -> T
+}
+
+impl<T, S> Zipper<T, S> {
+    /// A zipper focused on the root of `tree`, where `unfold` splits a node
+    /// into its shape and its children and `refill` puts them back together.
+    pub fn new(tree: T, unfold: fn(T) -> (S, Vec<T>), refill: fn(S, Vec<T>) -> T) -> Self {
+        Self {
+            focus: Some(tree),
+            frames: Vec::new(),
+            unfold,
+            refill
+        }
+    }
+
+    /// The focused node.
+    pub fn focus(&self) -> &T {
+        self.focus.as_ref().unwrap()
+    }
+
+    /// The focused node, to edit it in place.
+    pub fn focus_mut(&mut self) -> &mut T {
+        self.focus.as_mut().unwrap()
+    }
+
+    /// Replace the focused node by `new`, returning it.
+    pub fn replace(&mut self, new: T) -> T {
+        std::mem::replace(self.focus_mut(), new)
+    }
+
+    /// Whether the focus is on the root.
+    pub fn is_root(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The depth of the focus, the root being at depth `0`.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The index of the focus among its siblings, in the order of
+    /// `iter_children`, or `None` at the root.
+    pub fn index(&self) -> Option<usize> {
+        self.frames.last().map(|frame| frame.left.len())
+    }
+
+    /// The path from the root to the focus.
+    pub fn path(&self) -> TreePath {
+        self.frames.iter().map(|frame| frame.left.len()).collect::<Vec<_>>().into()
+    }
+
+    /// Move the focus down to the child at `index` of the focused node.
+    /// Returns `false` and stays put if there is no such child.
+    pub fn down(&mut self, index: usize) -> bool {
+        let (shape, mut children) = (self.unfold)(self.focus.take().unwrap());
+        if index >= children.len() {
+            self.focus = Some((self.refill)(shape, children));
+            return false
+        }
+        let mut right = children.split_off(index + 1);
+        right.reverse();
+        let focus = children.pop();
+        self.frames.push(Frame { shape, left: children, right });
+        self.focus = focus;
+        true
+    }
+
+    /// Move the focus up to the parent of the focused node, putting it back
+    /// in its place. Returns `false` and stays put at the root.
+    pub fn up(&mut self) -> bool {
+        let Frame { shape, mut left, right } = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return false
+        };
+        left.extend(self.focus.take());
+        left.extend(right.into_iter().rev());
+        self.focus = Some((self.refill)(shape, left));
+        true
+    }
+
+    /// Move the focus to the previous sibling of the focused node. Returns
+    /// `false` and stays put if there is none.
+    pub fn left(&mut self) -> bool {
+        match self.frames.last_mut() {
+            Some(Frame { left, right, .. }) if !left.is_empty() => {
+                right.extend(self.focus.take());
+                self.focus = left.pop();
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// Move the focus to the next sibling of the focused node. Returns
+    /// `false` and stays put if there is none.
+    pub fn right(&mut self) -> bool {
+        match self.frames.last_mut() {
+            Some(Frame { left, right, .. }) if !right.is_empty() => {
+                left.extend(self.focus.take());
+                self.focus = right.pop();
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// Move the focus back up to the root, rebuilding the tree.
+    pub fn into_tree(mut self) -> T {
+        while self.up() {}
+        self.focus.unwrap()
+    }
+}